@@ -0,0 +1,336 @@
+use crate::canvas::Color;
+use crate::export::{AlphaMode, ExportOptions};
+use crate::formats;
+use crate::frame::Frame;
+use crate::material::Material;
+use crate::matrix::Matrix;
+use crate::object::Intersectable;
+use crate::ray::Ray;
+use crate::world::World;
+use std::io::{self, Write};
+
+// Arbitrary output variables: extra buffers rendered in the same pass as the
+// beauty image, all taken from the primary hit of each camera ray. They are
+// filtered over the pixel's samples like colour, so pixels on the edge of an
+// object mix the values of everything they cover. Rays that miss or hit a
+// holdout give 0. Direct and indirect light add up to the beauty image, as
+// long as there is no fog between the camera and the hit.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Aov {
+  // Distance from the camera along its view axis
+  Depth,
+  // World space normal on the side facing the camera
+  Normal,
+  // Surface colour before lighting
+  Albedo,
+  // 1 + the object's index in the world
+  ObjectId,
+  // 1 + the index of the object's material among the world's distinct
+  // materials, in object order
+  MaterialId,
+  // Light arriving straight from the lights, ambient included
+  Direct,
+  // Reflected and refracted light
+  Indirect,
+  // Direct light from a single light, by its index in the world
+  Light(usize),
+}
+
+impl Aov {
+  pub fn channels(self) -> usize {
+    match self {
+      Aov::Depth | Aov::ObjectId | Aov::MaterialId => 1,
+      _ => 3,
+    }
+  }
+
+  pub fn name(self) -> String {
+    match self {
+      Aov::Depth => String::from("depth"),
+      Aov::Normal => String::from("normal"),
+      Aov::Albedo => String::from("albedo"),
+      Aov::ObjectId => String::from("object_id"),
+      Aov::MaterialId => String::from("material_id"),
+      Aov::Direct => String::from("direct"),
+      Aov::Indirect => String::from("indirect"),
+      Aov::Light(i) => format!("light{}", i),
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Aov> {
+    match name {
+      "depth" => Some(Aov::Depth),
+      "normal" => Some(Aov::Normal),
+      "albedo" => Some(Aov::Albedo),
+      "object_id" => Some(Aov::ObjectId),
+      "material_id" => Some(Aov::MaterialId),
+      "direct" => Some(Aov::Direct),
+      "indirect" => Some(Aov::Indirect),
+      _ => name.strip_prefix("light")?.parse().ok().map(Aov::Light),
+    }
+  }
+
+  // Names of the layer's channels in an EXR file
+  pub fn channel_names(self) -> Vec<String> {
+    match self {
+      Aov::Depth => vec![String::from("depth.Z")],
+      Aov::ObjectId | Aov::MaterialId => vec![format!("{}.Y", self.name())],
+      _ => ["R", "G", "B"].iter().map(|c| format!("{}.{}", self.name(), c)).collect(),
+    }
+  }
+}
+
+// Number of values a pixel stores for `aovs`.
+pub fn stride(aovs: &[Aov]) -> usize {
+  aovs.iter().map(|a| a.channels()).sum()
+}
+
+// The AOVs a render fills in, together with the lookup tables they need,
+// worked out once up front instead of for every sample.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AovSet {
+  pub aovs: Vec<Aov>,
+  // Material ID of every object, by the object's index
+  material_ids: Vec<usize>,
+}
+
+impl AovSet {
+  pub fn new(world: &World, aovs: &[Aov]) -> AovSet {
+    let mut distinct: Vec<Material> = Vec::new();
+    let mut material_ids = Vec::with_capacity(world.objects.len());
+
+    if aovs.contains(&Aov::MaterialId) {
+      for m in world.objects.iter().map(|o| o.material()) {
+        let id = match distinct.iter().position(|d| *d == m) {
+          Some(i) => i + 1,
+          None => {
+            distinct.push(m);
+            distinct.len()
+          }
+        };
+
+        material_ids.push(id);
+      }
+    }
+
+    AovSet { aovs: aovs.to_vec(), material_ids }
+  }
+
+  pub fn stride(&self) -> usize {
+    stride(&self.aovs)
+  }
+}
+
+impl World {
+  // The colour and coverage of a camera ray and the values of `aovs`, one
+  // after the other, all from the same trace. `view` is the camera's
+  // transform, which depth is measured in.
+  pub fn trace_with_aovs(&self, r: Ray, view: Matrix, aovs: &AovSet, remaining: u8) -> (Color, f64, Vec<f64>) {
+    let (color, alpha, primary) = self.trace_primary(r, remaining);
+    let (comps, direct, indirect) = match primary {
+      Some(primary) => primary,
+      None => return (color, alpha, vec![0.0; aovs.stride()]),
+    };
+    let material = comps.object.material();
+
+    let mut values = Vec::with_capacity(aovs.stride());
+    for aov in &aovs.aovs {
+      let color = match *aov {
+        Aov::Depth => {
+          values.push(-(view * comps.point).z);
+          continue;
+        }
+        Aov::ObjectId => {
+          values.push(comps.index as f64 + 1.0);
+          continue;
+        }
+        Aov::MaterialId => {
+          values.push(aovs.material_ids[comps.index] as f64);
+          continue;
+        }
+        Aov::Normal => Color {
+          r: comps.normal.x,
+          g: comps.normal.y,
+          b: comps.normal.z,
+        },
+        Aov::Albedo => material.color_at_object(&comps.object.at_time(comps.ray_time), comps.point),
+        Aov::Direct => direct,
+        Aov::Indirect => indirect,
+        // Shadow rays don't draw random numbers, so this leaves the
+        // beauty image alone
+        Aov::Light(i) => match self.lights.get(i) {
+          Some(light) => self.light_contribution(&comps, *light).1,
+          None => Color { r: 0.0, g: 0.0, b: 0.0 },
+        },
+      };
+
+      values.extend([color.r, color.g, color.b]);
+    }
+
+    (color, alpha, values)
+  }
+}
+
+// A multi-layer EXR with the beauty image as R, G, B (and A unless
+// `options.alpha` is opaque) and every AOV of the frame as its own layer.
+// Only the beauty image gets the exposure, AOVs are written as they are.
+pub fn write_exr_layers(frame: &Frame, sink: &mut impl Write, options: &ExportOptions) -> io::Result<()> {
+  let size = (frame.width * frame.height) as usize;
+  let mut channels: Vec<(String, Vec<f32>)> = Vec::new();
+
+  let beauty: Vec<(Color, f64)> = (0..size as u32)
+    .map(|i| {
+      let sample = frame.sample_at(i % frame.width, i / frame.width);
+
+      options.linear_with_alpha(sample.color, sample.alpha)
+    })
+    .collect();
+  channels.push((String::from("R"), beauty.iter().map(|(c, _)| c.r as f32).collect()));
+  channels.push((String::from("G"), beauty.iter().map(|(c, _)| c.g as f32).collect()));
+  channels.push((String::from("B"), beauty.iter().map(|(c, _)| c.b as f32).collect()));
+  if options.alpha != AlphaMode::Opaque {
+    channels.push((String::from("A"), beauty.iter().map(|(_, alpha)| *alpha as f32).collect()));
+  }
+
+  let values: Vec<Vec<f64>> = (0..size as u32).map(|i| frame.aovs_at(i % frame.width, i / frame.width)).collect();
+  let mut offset = 0;
+  for aov in &frame.aovs {
+    for name in aov.channel_names() {
+      channels.push((name, values.iter().map(|v| v[offset] as f32).collect()));
+      offset += 1;
+    }
+  }
+
+  channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+  formats::write_exr_channels(frame.width, frame.height, &channels, sink)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::aov::{stride, write_exr_layers, Aov, AovSet};
+  use crate::camera::Camera;
+  use crate::canvas::Color;
+  use crate::export::ExportOptions;
+  use crate::frame::Frame;
+  use crate::matrix::Matrix;
+  use crate::object::Object;
+  use crate::point::Point;
+  use crate::ray::Ray;
+  use crate::vector::Vector;
+  use crate::world::World;
+
+  fn ray_at_the_first_sphere() -> Ray {
+    Ray {
+      origin: Point { x: 0.0, y: 0.0, z: -5.0 },
+      direction: Vector { x: 0.0, y: 0.0, z: 1.0 },
+      time: 0.0,
+    }
+  }
+
+  #[test]
+  fn aovs_have_names_that_parse_back() {
+    for aov in [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId, Aov::Direct, Aov::Indirect, Aov::Light(3)] {
+      assert_eq!(Aov::from_name(&aov.name()), Some(aov));
+      assert_eq!(aov.channel_names().len(), aov.channels());
+    }
+
+    assert_eq!(Aov::from_name("lightx"), None);
+    assert_eq!(stride(&[Aov::Depth, Aov::Normal]), 4);
+  }
+
+  #[test]
+  fn aovs_come_from_the_primary_hit() {
+    let w = World::default();
+    let aovs = AovSet::new(&w, &[Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId]);
+
+    let view = Camera::view_transform(Point { x: 0.0, y: 0.0, z: -5.0 }, Point { x: 0.0, y: 0.0, z: 0.0 }, Vector { x: 0.0, y: 1.0, z: 0.0 });
+
+    let (_, _, values) = w.trace_with_aovs(ray_at_the_first_sphere(), view, &aovs, 5);
+
+    assert_eq!(values, vec![4.0, 0.0, 0.0, -1.0, 0.8, 1.0, 0.6, 1.0, 1.0]);
+  }
+
+  #[test]
+  fn objects_with_the_same_material_share_an_id() {
+    let mut w = World::default();
+    // A moving copy of the first sphere, off to the side
+    if let Object::Sphere(mut s) = w.objects[0] {
+      s.transform = Matrix::translate(3.0, 0.0, 0.0);
+      s.end_transform = Some(Matrix::translate(3.0, 0.5, 0.0));
+      w.objects.push(Object::Sphere(s));
+    }
+    let aovs = AovSet::new(&w, &[Aov::ObjectId, Aov::MaterialId]);
+    let r = Ray { origin: Point { x: 3.0, y: 0.0, z: -5.0 }, time: 0.5, ..ray_at_the_first_sphere() };
+
+    let (_, _, values) = w.trace_with_aovs(r, Matrix::identity(), &aovs, 5);
+
+    assert_eq!(aovs.material_ids, vec![1, 2, 1]);
+    assert_eq!(values, vec![3.0, 1.0]);
+  }
+
+  #[test]
+  fn direct_and_indirect_light_add_up_to_the_beauty() {
+    let mut w = World::default();
+    if let Object::Sphere(ref mut s) = w.objects[0] {
+      s.material.reflective = 0.5;
+    }
+    let r = ray_at_the_first_sphere();
+    let aovs = AovSet::new(&w, &[Aov::Direct, Aov::Indirect, Aov::Light(0)]);
+
+    let (color, _, values) = w.trace_with_aovs(r, Matrix::identity(), &aovs, 5);
+    let direct = Color { r: values[0], g: values[1], b: values[2] };
+    let indirect = Color { r: values[3], g: values[4], b: values[5] };
+
+    assert_eq!(direct + indirect, color);
+    assert_eq!(color, w.color_at(r, 5));
+    assert_eq!(Color { r: values[6], g: values[7], b: values[8] }, direct);
+  }
+
+  #[test]
+  fn aovs_of_rough_materials_come_from_the_same_random_rays() {
+    let mut w = World::default();
+    if let Object::Sphere(ref mut s) = w.objects[0] {
+      s.material.reflective = 0.5;
+      s.material.roughness = 0.3;
+    }
+    let r = ray_at_the_first_sphere();
+    let aovs = AovSet::new(&w, &[Aov::Direct, Aov::Indirect]);
+
+    crate::rng::seed(7);
+    let (color, _, values) = w.trace_with_aovs(r, Matrix::identity(), &aovs, 5);
+    crate::rng::seed(7);
+    let beauty = w.color_at(r, 5);
+
+    let direct = Color { r: values[0], g: values[1], b: values[2] };
+    let indirect = Color { r: values[3], g: values[4], b: values[5] };
+    assert_eq!(color, beauty);
+    assert_eq!(direct + indirect, beauty);
+  }
+
+  #[test]
+  fn misses_give_zero() {
+    let w = World::default();
+    let r = Ray {
+      origin: Point { x: 0.0, y: 0.0, z: -5.0 },
+      direction: Vector { x: 0.0, y: 1.0, z: 0.0 },
+      time: 0.0,
+    };
+    let aovs = AovSet::new(&w, &[Aov::Depth, Aov::Normal]);
+
+    assert_eq!(w.trace_with_aovs(r, Matrix::identity(), &aovs, 5).2, vec![0.0; 4]);
+  }
+
+  #[test]
+  fn exr_layers_are_named_by_aov() {
+    let f = Frame::with_aovs(1, 1, &[Aov::Depth, Aov::Normal]);
+    let mut out = Vec::new();
+
+    write_exr_layers(&f, &mut out, &ExportOptions::new()).unwrap();
+
+    let text = String::from_utf8_lossy(&out);
+    for name in ["B", "G", "R", "depth.Z", "normal.B", "normal.G", "normal.R"] {
+      assert!(text.contains(&format!("{}\0", name)));
+    }
+  }
+}
@@ -1,12 +1,21 @@
-use crate::canvas::{Canvas, Color};
+use crate::aov::AovSet;
+use crate::canvas::Canvas;
+use crate::filter::Filter;
+use crate::frame::{Frame, Sample, TileView};
+use crate::intersection::Intersection;
 use crate::matrix::Matrix;
 use crate::point::Point;
+use crate::progress::{Progress, RenderOptions};
 use crate::ray::Ray;
+use crate::ray_tree::RayNode;
+use crate::rng::{self, random};
+use crate::sampler::{AdaptiveSampling, Sampler};
+use crate::tile::{self, Tile, TileOrder};
 use crate::vector::Vector;
 use crate::world::World;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::thread;
+use std::time::Instant;
 
 use rayon::prelude::*;
 
@@ -19,12 +28,53 @@ pub struct Camera {
   pub pixel_size: f64,
   pub half_height: f64,
   pub half_width: f64,
+  pub aperture: f64,
+  pub focal_distance: f64,
+  // Rays traced per sample when the lens or the shutter needs sampling,
+  // spread over both at once.
+  pub camera_samples: u32,
+  pub projection: Projection,
+  pub eye: Eye,
+  pub interocular_distance: f64,
+  pub convergence_distance: f64,
+  // Where the camera has moved to when the shutter closes, if it moves.
+  pub end_transform: Option<Matrix>,
+  // Objects sit at their transform at time 0 and their end_transform at 1.
+  pub shutter_open: f64,
+  pub shutter_close: f64,
+  pub sampler: Sampler,
+  pub filter: Filter,
+  pub adaptive: Option<AdaptiveSampling>,
+  pub tile_size: u32,
+  pub tile_order: TileOrder,
+  // Only pixels inside the region are traced, the rest of the frame is left
+  // black.
+  pub region: Option<Tile>,
 }
 
-pub struct CoordColor {
-  x: u32,
-  y: u32,
-  c: Color,
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Projection {
+  Perspective,
+  // Parallel rays covering 2 * half_width units across the image.
+  Orthographic,
+  // Equidistant fisheye, `fov` (up to 2 pi) spans the image width.
+  Fisheye,
+  // Full 360x180 degree latitude/longitude panorama.
+  Equirectangular,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Eye {
+  Center,
+  Left,
+  Right,
+}
+
+#[allow(dead_code, reason = "the demo renders a single eye")]
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum StereoLayout {
+  SideBySide,
+  OverUnder,
 }
 
 impl Camera {
@@ -52,66 +102,575 @@ impl Camera {
       half_width,
       half_height,
       pixel_size,
+      aperture: 0.0,
+      focal_distance: 1.0,
+      camera_samples: 16,
+      projection: Projection::Perspective,
+      eye: Eye::Center,
+      interocular_distance: 0.0,
+      convergence_distance: 1.0,
+      end_transform: None,
+      shutter_open: 0.0,
+      shutter_close: 0.0,
+      sampler: Sampler::Center,
+      filter: Filter::Box,
+      adaptive: None,
+      tile_size: 32,
+      tile_order: TileOrder::Spiral,
+      region: None,
     }
   }
 
+  #[allow(dead_code, reason = "Scenes pick a projection in code, the demo in main.rs is perspective")]
+  pub fn orthographic(hsize: u32, vsize: u32, view_width: f64) -> Camera {
+    let mut c = Camera::new(hsize, vsize, 0.0);
+    c.projection = Projection::Orthographic;
+    c.half_width = view_width / 2.0;
+    c.half_height = c.half_width * vsize as f64 / hsize as f64;
+    c.pixel_size = view_width / hsize as f64;
+
+    c
+  }
+
+  #[allow(dead_code, reason = "Scenes pick a projection in code, the demo in main.rs is perspective")]
+  pub fn fisheye(hsize: u32, vsize: u32, fov: f64) -> Camera {
+    let mut c = Camera::new(hsize, vsize, fov);
+    c.projection = Projection::Fisheye;
+
+    c
+  }
+
+  #[allow(dead_code, reason = "Scenes pick a projection in code, the demo in main.rs is perspective")]
+  pub fn equirectangular(hsize: u32, vsize: u32) -> Camera {
+    let mut c = Camera::new(hsize, vsize, 2.0 * std::f64::consts::PI);
+    c.projection = Projection::Equirectangular;
+
+    c
+  }
+
   pub fn ray_for_pixel(&self, px: u32, py: u32, ox: f64, oy: f64) -> Ray {
+    self.ray_through_lens(px, py, ox, oy, 0.0, 0.0)
+  }
+
+  // Thin lens model. (lx, ly) is a point on the unit disc that gets scaled by
+  // the aperture; every ray through a pixel meets on the focal plane. Only
+  // perspective cameras have a lens, the other projections ignore it.
+  pub fn ray_through_lens(&self, px: u32, py: u32, ox: f64, oy: f64, lx: f64, ly: f64) -> Ray {
     let x_offset = (px as f64 + ox) * self.pixel_size;
     let y_offset = (py as f64 + oy) * self.pixel_size;
 
     let world_x = self.half_width - x_offset;
     let world_y = self.half_height - y_offset;
 
-    let pixel = self.transform.inverse().unwrap()
-      * Point {
-        x: world_x,
-        y: world_y,
-        z: -1.0,
-      };
-    let origin = self.transform.inverse().unwrap()
-      * Point {
+    let eye = Point {
+      x: self.eye_shift(),
+      y: 0.0,
+      z: 0.0,
+    };
+
+    let (origin, direction) = match self.projection {
+      Projection::Perspective => {
+        // Off-axis stereo: both eyes look through the same window on the
+        // convergence plane instead of turning inwards.
+        let converge = Point {
+          x: world_x * self.convergence_distance,
+          y: world_y * self.convergence_distance,
+          z: -self.convergence_distance,
+        };
+        let focus = eye + (converge - eye) * (self.focal_distance / self.convergence_distance);
+        let lens = Point {
+          x: eye.x + lx * self.aperture,
+          y: ly * self.aperture,
+          z: 0.0,
+        };
+
+        (lens, focus - lens)
+      }
+      Projection::Orthographic => (
+        Point {
+          x: world_x + eye.x,
+          y: world_y,
+          z: 0.0,
+        },
+        Vector {
+          x: 0.0,
+          y: 0.0,
+          z: -1.0,
+        },
+      ),
+      Projection::Fisheye => (eye, self.fisheye_direction(px, py, ox, oy)),
+      Projection::Equirectangular => {
+        // Omnidirectional stereo: each eye sits on a circle, offset at right
+        // angles to the direction it is looking in.
+        let direction = self.panorama_direction(px, py, ox, oy);
+        let horizontal = Vector {
+          x: direction.x,
+          y: 0.0,
+          z: direction.z,
+        };
+        let origin = if horizontal.magnitude() == 0.0 {
+          eye
+        } else {
+          let h = horizontal.normalize();
+
+          Point {
+            x: -h.z * eye.x,
+            y: 0.0,
+            z: h.x * eye.x,
+          }
+        };
+
+        (origin, direction)
+      }
+    };
+
+    let inverse = self.transform.inverse().unwrap();
+
+    Ray {
+      origin: inverse * origin,
+      direction: (inverse * direction).normalize(),
+      time: self.shutter_open,
+    }
+  }
+
+  // Distance from the image centre in units of half the image width, and the
+  // pixel's offset from the centre.
+  fn polar_offset(&self, px: u32, py: u32, ox: f64, oy: f64) -> (f64, f64, f64) {
+    let half = self.hsize as f64 / 2.0;
+    let dx = px as f64 + ox - half;
+    let dy = py as f64 + oy - self.vsize as f64 / 2.0;
+
+    ((dx * dx + dy * dy).sqrt() / half, dx, dy)
+  }
+
+  fn fisheye_direction(&self, px: u32, py: u32, ox: f64, oy: f64) -> Vector {
+    let (r, dx, dy) = self.polar_offset(px, py, ox, oy);
+
+    if r == 0.0 {
+      return Vector {
         x: 0.0,
         y: 0.0,
-        z: 0.0,
+        z: -1.0,
       };
-    let direction = (pixel - origin).normalize();
+    }
 
-    Ray { origin, direction }
+    let theta = (r * self.fov / 2.0).min(std::f64::consts::PI);
+    let radius = dx.hypot(dy);
+
+    Vector {
+      x: -theta.sin() * dx / radius,
+      y: -theta.sin() * dy / radius,
+      z: -theta.cos(),
+    }
   }
 
-  pub fn render(self, w: World, antialias: bool, max_steps: u8) -> Canvas {
-    let mut canvas = Canvas::new(self.hsize, self.vsize);
+  fn panorama_direction(&self, px: u32, py: u32, ox: f64, oy: f64) -> Vector {
+    let u = (px as f64 + ox) / self.hsize as f64;
+    let v = (py as f64 + oy) / self.vsize as f64;
 
-    let coord_colors = Arc::new(Mutex::new(Vec::new()));
+    let longitude = (0.5 - u) * 2.0 * std::f64::consts::PI;
+    let latitude = (0.5 - v) * std::f64::consts::PI;
 
-    (0..self.vsize).into_par_iter().for_each(|y| {
-      for x in 0..self.hsize {
-        let c: Color;
+    Vector {
+      x: longitude.sin() * latitude.cos(),
+      y: latitude.sin(),
+      z: -longitude.cos() * latitude.cos(),
+    }
+  }
+
+  // Camera space x points left, so the left eye sits on the positive side.
+  fn eye_shift(&self) -> f64 {
+    match self.eye {
+      Eye::Center => 0.0,
+      Eye::Left => self.interocular_distance / 2.0,
+      Eye::Right => -self.interocular_distance / 2.0,
+    }
+  }
+
+  // Fisheye images are a disc, samples outside it see nothing.
+  pub fn in_view(&self, px: u32, py: u32, ox: f64, oy: f64) -> bool {
+    match self.projection {
+      Projection::Fisheye => self.polar_offset(px, py, ox, oy).0 <= 1.0,
+      _ => true,
+    }
+  }
+
+  // Sets the focal distance to the depth of whatever is visible through the
+  // centre of the given pixel. Returns the new distance, or None if the ray
+  // hits nothing.
+  #[allow(dead_code, reason = "the demo scene sets no aperture, so there is nothing to focus")]
+  pub fn focus_on(&mut self, w: &World, px: u32, py: u32) -> Option<f64> {
+    let r = self.ray_for_pixel(px, py, 0.5, 0.5);
+    let hit = Intersection::hit(w.intersect(r))?;
+
+    let camera_point = self.transform * r.position(hit.time);
+    self.focal_distance = -camera_point.z;
+
+    Some(self.focal_distance)
+  }
+
+  // The sample seen through (ox, oy) within the pixel, and the values of
+  // `aovs` along the same rays.
+  fn color_for_sample(&self, w: &World, px: u32, py: u32, (ox, oy): (f64, f64), max_steps: u8, aovs: &AovSet) -> (Sample, Vec<f64>) {
+    if !self.in_view(px, py, ox, oy) {
+      return (Sample::transparent(), vec![0.0; aovs.stride()]);
+    }
+
+    let has_lens = self.aperture != 0.0 && self.projection == Projection::Perspective;
+    let has_shutter = self.shutter_close > self.shutter_open;
+
+    if !has_lens && !has_shutter {
+      return self.trace(w, self.ray_for_pixel(px, py, ox, oy), max_steps, aovs);
+    }
 
-        if antialias {
-          let mut color = Color {
-            r: 0.0,
-            g: 0.0,
-            b: 0.0,
-          };
-          for i in 0..2 {
-            for j in 0..2 {
-              let r = self.ray_for_pixel(x, y, 0.25 + (i as f64 * 0.5), 0.25 + (j as f64 * 0.5));
-              color = color + w.color_at(r, max_steps);
+    let samples = self.camera_samples.max(1);
+    let mut sample = Sample::transparent();
+    let mut values = vec![0.0; aovs.stride()];
+
+    for _ in 0..samples {
+      let (lx, ly) = if has_lens { concentric_disk(random(), random()) } else { (0.0, 0.0) };
+      let camera = self.at_time(self.shutter_open + random() * (self.shutter_close - self.shutter_open));
+
+      let (s, v) = camera.trace(w, camera.ray_through_lens(px, py, ox, oy, lx, ly), max_steps, aovs);
+      sample = sample + s;
+      values.iter_mut().zip(v).for_each(|(sum, v)| *sum += v);
+    }
+
+    let scale = 1.0 / samples as f64;
+    values.iter_mut().for_each(|v| *v *= scale);
+
+    (sample * scale, values)
+  }
+
+  fn trace(&self, w: &World, r: Ray, max_steps: u8, aovs: &AovSet) -> (Sample, Vec<f64>) {
+    if aovs.aovs.is_empty() {
+      let (color, alpha) = w.color_and_alpha_at(r, max_steps);
+      return (Sample { color, alpha }, Vec::new());
+    }
+
+    let (color, alpha, values) = w.trace_with_aovs(r, self.transform, aovs, max_steps);
+
+    (Sample { color, alpha }, values)
+  }
+
+  // Spreads the sampler's offsets over the filter's footprint around the
+  // pixel centre and weights the samples by the filter. In adaptive mode
+  // batches of samples keep coming until the pixel's luminance settles.
+  // Every batch after the first is shifted by a random amount, wrapping
+  // around the pixel (a Cranley-Patterson rotation), so that samplers with a
+  // fixed pattern still trace somewhere new each time.
+  // Returns the weighted sum of the samples, the total weight and the number
+  // of samples traced; dividing the first two gives the pixel's colour and
+  // coverage. The weighted sums of the AOVs come second.
+  fn color_for_pixel(&self, w: &World, px: u32, py: u32, max_steps: u8, aovs: &AovSet) -> (Sample, Vec<f64>, f64, u32) {
+    let radius = self.filter.radius();
+    let mut sum = Sample::transparent();
+    let mut aov_sums = vec![0.0; aovs.stride()];
+    let mut total_weight = 0.0;
+
+    let mut taken = 0;
+    let mut mean = 0.0;
+    let mut squared_deviations = 0.0;
+
+    loop {
+      let (sx, sy) = if taken == 0 { (0.0, 0.0) } else { (random(), random()) };
+      let before = taken;
+
+      for (u, v) in self.sampler.offsets() {
+        let (u, v) = ((u + sx).fract(), (v + sy).fract());
+        let dx = (u - 0.5) * 2.0 * radius;
+        let dy = (v - 0.5) * 2.0 * radius;
+        let weight = self.filter.weight(dx, dy);
+
+        if weight == 0.0 {
+          continue;
+        }
+
+        let (sample, values) = self.color_for_sample(w, px, py, (0.5 + dx, 0.5 + dy), max_steps, aovs);
+        sum = sum + sample * weight;
+        aov_sums.iter_mut().zip(values).for_each(|(s, v)| *s += v * weight);
+        total_weight += weight;
+
+        taken += 1;
+        let luminance = sample.color.luminance();
+        let delta = luminance - mean;
+        mean += delta / taken as f64;
+        squared_deviations += delta * (luminance - mean);
+      }
+
+      match self.adaptive {
+        None => break,
+        Some(a) => {
+          if taken >= a.max_samples || taken == before {
+            break;
+          }
+
+          if taken >= a.min_samples.max(2) {
+            let standard_error = (squared_deviations / (taken - 1) as f64 / taken as f64).sqrt();
+
+            if standard_error <= a.threshold {
+              break;
             }
           }
-          c = color * (1.0 / 4.0);
-        } else {
-          let r = self.ray_for_pixel(x, y, 0.5, 0.5);
-          c = w.color_at(r, max_steps);
         }
+      }
+    }
+
+    (sum, aov_sums, total_weight, taken)
+  }
+
+  // A copy of the camera frozen at `time`, with an instantaneous shutter.
+  pub fn at_time(&self, time: f64) -> Camera {
+    let mut c = *self;
+
+    if let Some(end) = self.end_transform {
+      c.transform = self.transform.interpolate(&end, time);
+      c.end_transform = None;
+    }
+    c.shutter_open = time;
+    c.shutter_close = time;
+
+    c
+  }
+
+  #[allow(dead_code, reason = "main.rs needs the options render_with takes")]
+  pub fn render(self, w: World, max_steps: u8) -> Canvas {
+    self.render_frame(w, max_steps).canvas()
+  }
+
+  pub fn render_frame(self, w: World, max_steps: u8) -> Frame {
+    self.render_with(w, max_steps, &RenderOptions::new())
+  }
+
+  // Renders a single pass. If the render is cancelled or runs out of time the
+  // tiles finished so far are returned, the rest of the frame is left black.
+  pub fn render_with(self, w: World, max_steps: u8, options: &RenderOptions) -> Frame {
+    let mut frame = Frame::with_aovs(self.hsize, self.vsize, &options.aovs);
+    self.render_pass(&w, max_steps, &mut frame, options, Instant::now());
 
-        coord_colors.lock().unwrap().push(CoordColor { x, y, c });
+    frame
+  }
+
+  // Keeps adding passes to the frame until `passes` of them are done, or until
+  // the render is cancelled or out of time if `passes` is None. `on_pass` is
+  // called with the image so far after every finished pass.
+  #[allow(dead_code, reason = "main.rs always resumes, from a checkpoint or an empty frame")]
+  pub fn render_progressive(
+    self,
+    w: World,
+    max_steps: u8,
+    passes: Option<u32>,
+    options: &RenderOptions,
+    on_pass: impl FnMut(&Frame),
+  ) -> Frame {
+    self.resume_progressive(w, Frame::with_aovs(self.hsize, self.vsize, &options.aovs), max_steps, passes, options, on_pass)
+  }
+
+  // Carries on a progressive render from a frame that already has some
+  // passes in it, e.g. one loaded from a checkpoint.
+  pub fn resume_progressive(
+    self,
+    w: World,
+    mut frame: Frame,
+    max_steps: u8,
+    passes: Option<u32>,
+    options: &RenderOptions,
+    mut on_pass: impl FnMut(&Frame),
+  ) -> Frame {
+    let started = Instant::now();
+
+    while passes.is_none_or(|p| frame.passes < p) {
+      if !self.render_pass(&w, max_steps, &mut frame, options, started) {
+        break;
       }
-    });
 
-    for cc in coord_colors.lock().unwrap().iter() {
-      canvas.set_pixel(cc.x, cc.y, cc.c)
+      on_pass(&frame);
+    }
+
+    frame
+  }
+
+  // Adds one more pass to the frame. Splits the image into tiles and hands
+  // them out to the rayon pool in `tile_order`, every tile adds its samples
+  // straight into its own rows of the frame. Tiles already in
+  // `completed_tiles` are skipped, so an interrupted pass can be picked up
+  // where it stopped. `started` is when the whole render began, for the time
+  // budget; progress is reported for this pass alone. Returns whether the
+  // pass was finished.
+  pub fn render_pass(&self, w: &World, max_steps: u8, frame: &mut Frame, options: &RenderOptions, started: Instant) -> bool {
+    let region = self.region();
+    let tiles: Vec<Tile> = tile::tiles(self.hsize, self.vsize, self.tile_size, self.tile_order)
+      .into_iter()
+      .filter(|t| t.overlaps(&region))
+      .collect();
+    let pass = frame.passes;
+    let aovs = AovSet::new(w, &frame.aovs);
+
+    let pass_started = Instant::now();
+    let tiles_done = AtomicU32::new(0);
+    let rays_traced = AtomicU64::new(0);
+
+    let done = frame.completed_tiles.clone();
+    let remaining: Vec<_> = frame
+      .tiles_mut(&tiles, self.tile_size)
+      .into_iter()
+      .filter(|view| !done.contains(&view.tile))
+      .collect();
+    let tiles_total = remaining.len() as u32;
+
+    let completed: Vec<_> = remaining
+      .into_iter()
+      .par_bridge()
+      .filter_map(|view| {
+        if options.should_stop(started) {
+          return None;
+        }
+
+        let tile = view.tile;
+        let rays = self.render_tile(w, view, max_steps, pass, &aovs);
+
+        options.report(&Progress {
+          pass,
+          tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1,
+          tiles_total,
+          rays_traced: rays_traced.fetch_add(rays, Ordering::Relaxed) + rays,
+          elapsed: pass_started.elapsed(),
+        });
+
+        Some(tile)
+      })
+      .collect();
+    frame.completed_tiles.extend(completed);
+
+    if frame.completed_tiles.len() < tiles.len() {
+      return false;
+    }
+
+    frame.completed_tiles.clear();
+    frame.passes += 1;
+
+    true
+  }
+
+  // Returns the number of camera rays traced.
+  fn render_tile(&self, w: &World, view: TileView, max_steps: u8, pass: u32, aovs: &AovSet) -> u64 {
+    let tile = view.tile;
+    let region = self.region();
+    let mut rays = 0;
+
+    let stride = aovs.stride();
+
+    for (dy, row) in view.rows.into_iter().enumerate() {
+      let y = tile.y + dy as u32;
+
+      for dx in 0..row.sums.len() {
+        let x = tile.x + dx as u32;
+        if !region.contains(x, y) {
+          continue;
+        }
+
+        rng::seed(self.pixel_seed(pass, x, y));
+
+        let (sample, values, total_weight, samples) = self.color_for_pixel(w, x, y, max_steps, aovs);
+        row.sums[dx] = row.sums[dx] + sample;
+        row.weights[dx] += total_weight;
+        row.counts[dx] += samples;
+        row.aov_sums[dx * stride..(dx + 1) * stride].iter_mut().zip(values).for_each(|(s, v)| *s += v);
+        rays += samples as u64 * self.rays_per_sample();
+      }
+    }
+
+    rays
+  }
+
+  // Every pixel gets its own sequence per pass, so renders are repeatable
+  // however the tiles are scheduled.
+  fn pixel_seed(&self, pass: u32, x: u32, y: u32) -> u64 {
+    ((pass as u64) << 32) ^ (y * self.hsize + x) as u64
+  }
+
+  fn rays_per_sample(&self) -> u64 {
+    let has_lens = self.aperture != 0.0 && self.projection == Projection::Perspective;
+    let has_shutter = self.shutter_close > self.shutter_open;
+
+    if has_lens || has_shutter {
+      self.camera_samples.max(1) as u64
+    } else {
+      1
+    }
+  }
+
+  // The render region clipped to the image, or the whole image.
+  pub fn region(&self) -> Tile {
+    let full = Tile {
+      x: 0,
+      y: 0,
+      width: self.hsize,
+      height: self.vsize,
+    };
+
+    match self.region {
+      Some(r) => {
+        let x = r.x.min(self.hsize);
+        let y = r.y.min(self.vsize);
+
+        Tile {
+          x,
+          y,
+          width: r.width.min(self.hsize - x),
+          height: r.height.min(self.vsize - y),
+        }
+      }
+      None => full,
+    }
+  }
+
+  // Renders just the region, into a canvas the size of the region.
+  #[allow(dead_code, reason = "region renders are driven from code while tweaking a scene")]
+  pub fn render_region(self, w: World, max_steps: u8) -> Canvas {
+    self.render_frame(w, max_steps).crop(self.region())
+  }
+
+  // Renders just the region and pastes it over the same pixels of `canvas`,
+  // which must be the size of the whole image.
+  #[allow(dead_code, reason = "region renders are driven from code while tweaking a scene")]
+  pub fn render_into(self, w: World, max_steps: u8, canvas: &mut Canvas) {
+    canvas.blit(&self.render_region(w, max_steps), self.region().x, self.region().y);
+  }
+
+  // Traces a single ray through the middle of a pixel and records everything
+  // that happens to it and the rays it spawns. The random numbers are the
+  // ones the pixel gets in the first pass of a render, so with the default
+  // sampler the tree's colour is the rendered one.
+  pub fn debug_pixel(&self, w: &World, px: u32, py: u32, max_steps: u8) -> RayNode {
+    rng::seed(self.pixel_seed(0, px, py));
+
+    w.ray_tree(self.ray_for_pixel(px, py, 0.5, 0.5), max_steps)
+  }
+
+  // Renders both eyes into a single canvas, left eye on the left or on top.
+  #[allow(dead_code, reason = "the demo renders a single eye")]
+  pub fn render_stereo(self, w: World, layout: StereoLayout, max_steps: u8) -> Canvas {
+    let mut left = self;
+    left.eye = Eye::Left;
+    let mut right = self;
+    right.eye = Eye::Right;
+
+    let left_image = left.render(w.clone(), max_steps);
+    let right_image = right.render(w, max_steps);
+
+    let mut canvas;
+    match layout {
+      StereoLayout::SideBySide => {
+        canvas = Canvas::new(self.hsize * 2, self.vsize);
+        canvas.blit(&left_image, 0, 0);
+        canvas.blit(&right_image, self.hsize, 0);
+      }
+      StereoLayout::OverUnder => {
+        canvas = Canvas::new(self.hsize, self.vsize * 2);
+        canvas.blit(&left_image, 0, 0);
+        canvas.blit(&right_image, 0, self.vsize);
+      }
     }
 
     canvas
@@ -136,15 +695,46 @@ impl Camera {
   }
 }
 
+// Maps a point in the unit square to the unit disc while keeping samples
+// evenly spread (Shirley & Chiu).
+fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+  let a = 2.0 * u - 1.0;
+  let b = 2.0 * v - 1.0;
+
+  if a == 0.0 && b == 0.0 {
+    return (0.0, 0.0);
+  }
+
+  let (r, theta) = if a.abs() > b.abs() {
+    (a, std::f64::consts::FRAC_PI_4 * (b / a))
+  } else {
+    (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b))
+  };
+
+  (r * theta.cos(), r * theta.sin())
+}
+
 #[cfg(test)]
 mod tests {
-  use crate::camera::Camera;
+  use crate::camera::{Camera, Eye, Projection, StereoLayout};
+  use crate::aov::Aov;
+  use crate::filter::Filter;
+  use crate::sampler::{AdaptiveSampling, Sampler};
+  use crate::tile::TileOrder;
+  use crate::progress::{CancellationToken, RenderOptions};
+  use crate::frame::Frame;
+  use crate::tile::Tile;
+  use crate::canvas::Canvas;
+  use std::time::Instant;
+  use std::sync::Mutex;
   use crate::canvas::Color;
   use crate::matrix::Matrix;
   use crate::point::Point;
   use crate::utils::equal;
   use crate::vector::Vector;
+  use crate::object::Object;
   use crate::world::World;
+  use crate::rng;
 
   #[test]
   fn transformation_matrix_for_default_orientation() {
@@ -347,9 +937,9 @@ mod tests {
     assert_eq!(
       r.direction,
       Vector {
-        x: ((2.0 as f64).sqrt() / 2.0),
+        x: (2.0_f64.sqrt() / 2.0),
         y: 0.0,
-        z: -((2.0 as f64).sqrt() / 2.0)
+        z: -(2.0_f64.sqrt() / 2.0)
       }
     );
   }
@@ -376,15 +966,730 @@ mod tests {
     };
     c.transform = Camera::view_transform(from, to, up);
 
-    let image = c.render(w);
+    let image = c.render(w, 5);
 
     assert_eq!(
       image.get_pixel(5, 5),
       Color {
-        r: 0.38039216,
-        g: 0.4745098,
-        b: 0.28235295
+        r: 0.38066,
+        g: 0.47583,
+        b: 0.2855
+      }
+    );
+  }
+
+  #[test]
+  fn a_camera_defaults_to_a_pinhole() {
+    let c = Camera::new(160, 120, std::f64::consts::PI / 2.0);
+
+    assert_eq!(c.aperture, 0.0);
+    assert_eq!(c.focal_distance, 1.0);
+  }
+
+  #[test]
+  fn lens_rays_converge_on_the_focal_plane() {
+    let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+    c.aperture = 0.5;
+    c.focal_distance = 4.0;
+
+    let center = c.ray_for_pixel(10, 20, 0.5, 0.5);
+    let edge = c.ray_through_lens(10, 20, 0.5, 0.5, 1.0, 0.0);
+
+    let t_center = -4.0 / center.direction.z;
+    let t_edge = -4.0 / edge.direction.z;
+
+    assert_eq!(edge.origin, Point { x: 0.5, y: 0.0, z: 0.0 });
+    assert_eq!(center.position(t_center), edge.position(t_edge));
+  }
+
+  #[test]
+  fn focusing_on_the_object_under_a_pixel() {
+    let w = World::default();
+    let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+
+    let distance = c.focus_on(&w, 5, 5);
+
+    assert!(equal(distance.unwrap(), 4.0));
+    assert!(equal(c.focal_distance, 4.0));
+    assert_eq!(c.focus_on(&w, 0, 0), None);
+  }
+
+  #[test]
+  fn orthographic_rays_are_parallel() {
+    let c = Camera::orthographic(200, 100, 10.0);
+
+    let center = c.ray_for_pixel(100, 50, 0.0, 0.0);
+    let corner = c.ray_for_pixel(0, 0, 0.0, 0.0);
+
+    assert_eq!(c.projection, Projection::Orthographic);
+    assert_eq!(center.direction, corner.direction);
+    assert_eq!(
+      center.origin,
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0
+      }
+    );
+    assert_eq!(
+      corner.origin,
+      Point {
+        x: 5.0,
+        y: 2.5,
+        z: 0.0
+      }
+    );
+  }
+
+  #[test]
+  fn fisheye_edge_looks_sideways_at_half_the_fov() {
+    let c = Camera::fisheye(200, 200, std::f64::consts::PI);
+
+    let center = c.ray_for_pixel(100, 100, 0.0, 0.0);
+    let right = c.ray_for_pixel(200, 100, 0.0, 0.0);
+
+    assert_eq!(
+      center.direction,
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0
+      }
+    );
+    assert_eq!(
+      right.direction,
+      Vector {
+        x: -1.0,
+        y: 0.0,
+        z: 0.0
+      }
+    );
+  }
+
+  #[test]
+  fn fisheye_corners_are_out_of_view() {
+    let c = Camera::fisheye(200, 200, 2.0 * std::f64::consts::PI);
+
+    assert!(c.in_view(100, 100, 0.5, 0.5));
+    assert!(!c.in_view(0, 0, 0.5, 0.5));
+  }
+
+  #[test]
+  fn equirectangular_covers_the_whole_sphere() {
+    let c = Camera::equirectangular(360, 180);
+
+    let forward = c.ray_for_pixel(180, 90, 0.0, 0.0);
+    let behind = c.ray_for_pixel(0, 90, 0.0, 0.0);
+    let up = c.ray_for_pixel(180, 0, 0.0, 0.0);
+
+    assert_eq!(
+      forward.direction,
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0
+      }
+    );
+    assert_eq!(
+      behind.direction,
+      Vector {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0
+      }
+    );
+    assert_eq!(
+      up.direction,
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0
       }
     );
   }
+
+  #[test]
+  fn view_transform_applies_to_every_projection() {
+    let transform = Matrix::rotate_y(std::f64::consts::PI / 2.0) * Matrix::translate(0.0, -2.0, 5.0);
+
+    for mut c in [
+      Camera::orthographic(101, 101, 2.0),
+      Camera::fisheye(101, 101, std::f64::consts::PI),
+      Camera::equirectangular(101, 101),
+    ] {
+      c.transform = transform;
+      let r = c.ray_for_pixel(50, 50, 0.5, 0.5);
+
+      assert_eq!(
+        r.origin,
+        Point {
+          x: 0.0,
+          y: 2.0,
+          z: -5.0
+        }
+      );
+      assert_eq!(
+        r.direction,
+        Vector {
+          x: 1.0,
+          y: 0.0,
+          z: 0.0
+        }
+      );
+    }
+  }
+
+  #[test]
+  fn stereo_eyes_are_offset_by_the_interocular_distance() {
+    let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+    c.interocular_distance = 0.5;
+
+    c.eye = Eye::Left;
+    let left = c.ray_for_pixel(100, 50, 0.5, 0.5);
+    c.eye = Eye::Right;
+    let right = c.ray_for_pixel(100, 50, 0.5, 0.5);
+
+    assert_eq!(left.origin - right.origin, Vector { x: 0.5, y: 0.0, z: 0.0 });
+  }
+
+  #[test]
+  fn stereo_eyes_converge_on_a_plane_without_toe_in() {
+    let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+    c.interocular_distance = 0.5;
+    c.convergence_distance = 3.0;
+
+    for (px, py) in [(100, 50), (0, 0), (200, 100)] {
+      c.eye = Eye::Left;
+      let left = c.ray_for_pixel(px, py, 0.5, 0.5);
+      c.eye = Eye::Right;
+      let right = c.ray_for_pixel(px, py, 0.5, 0.5);
+
+      let at_left = left.position(-3.0 / left.direction.z);
+      let at_right = right.position(-3.0 / right.direction.z);
+
+      assert_eq!(at_left, at_right);
+    }
+  }
+
+  #[test]
+  fn omnidirectional_stereo_offsets_eyes_sideways_to_each_column() {
+    let mut c = Camera::equirectangular(360, 180);
+    c.interocular_distance = 0.5;
+    c.eye = Eye::Left;
+
+    let forward = c.ray_for_pixel(180, 90, 0.0, 0.0);
+    let behind = c.ray_for_pixel(0, 90, 0.0, 0.0);
+
+    assert_eq!(
+      forward.origin,
+      Point {
+        x: 0.25,
+        y: 0.0,
+        z: 0.0
+      }
+    );
+    assert_eq!(
+      behind.origin,
+      Point {
+        x: -0.25,
+        y: 0.0,
+        z: 0.0
+      }
+    );
+  }
+
+  #[test]
+  fn stereo_layouts_double_the_image() {
+    let w = World::default();
+    let c = Camera::new(4, 3, std::f64::consts::PI / 2.0);
+
+    let side_by_side = c.render_stereo(w.clone(), StereoLayout::SideBySide, 5);
+    let over_under = c.render_stereo(w, StereoLayout::OverUnder, 5);
+
+    assert_eq!(side_by_side.width(), 8);
+    assert_eq!(side_by_side.height(), 3);
+    assert_eq!(over_under.width(), 4);
+    assert_eq!(over_under.height(), 6);
+  }
+
+  #[test]
+  fn a_moving_camera_is_frozen_at_the_ray_time() {
+    let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+    c.end_transform = Some(Matrix::translate(-4.0, 0.0, 0.0));
+    c.shutter_close = 1.0;
+
+    let r = c.at_time(0.5).ray_for_pixel(100, 50, 0.5, 0.5);
+
+    assert_eq!(
+      r.origin,
+      Point {
+        x: 2.0,
+        y: 0.0,
+        z: 0.0
+      }
+    );
+    assert_eq!(r.time, 0.5);
+  }
+
+  #[test]
+  fn a_camera_samples_pixel_centres_by_default() {
+    let c = Camera::new(160, 120, std::f64::consts::PI / 2.0);
+
+    assert_eq!(c.sampler, Sampler::Center);
+    assert_eq!(c.filter, Filter::Box);
+  }
+
+  #[test]
+  fn supersampling_a_flat_region_matches_a_single_sample() {
+    let w = World::default();
+    let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+
+    let single = c.render(w.clone(), 5).get_pixel(0, 0);
+
+    c.sampler = Sampler::MultiJitter(16);
+    c.filter = Filter::MitchellNetravali;
+    let supersampled = c.render(w, 5).get_pixel(0, 0);
+
+    assert_eq!(single, supersampled);
+  }
+
+  #[test]
+  fn adaptive_sampling_only_refines_noisy_pixels() {
+    let w = World::default();
+    let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+    c.sampler = Sampler::Stratified(2);
+    c.adaptive = Some(AdaptiveSampling {
+      min_samples: 4,
+      max_samples: 64,
+      threshold: 0.001,
+    });
+
+    let frame = c.render_frame(w, 5);
+
+    // Empty background settles straight away, the sphere's silhouette doesn't
+    assert_eq!(frame.samples_at(0, 0), 4);
+    assert!((0..11).any(|x| frame.samples_at(x, 5) == 64));
+  }
+
+  #[test]
+  fn adaptive_batches_of_a_fixed_grid_trace_new_offsets() {
+    let w = World::default();
+    let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+    c.sampler = Sampler::Grid(2);
+    let fixed = c.render_frame(w.clone(), 5);
+
+    c.adaptive = Some(AdaptiveSampling {
+      min_samples: 4,
+      max_samples: 64,
+      threshold: 0.001,
+    });
+    let refined = c.render_frame(w, 5);
+
+    // Repeating the same four offsets would only ever reproduce the fixed
+    // grid's colour
+    let edges: Vec<u32> = (0..11).filter(|&x| refined.samples_at(x, 5) > 4).collect();
+    assert!(!edges.is_empty());
+    assert!(edges.iter().any(|&x| refined.canvas().get_pixel(x, 5) != fixed.canvas().get_pixel(x, 5)));
+  }
+
+  #[test]
+  fn tile_size_and_order_do_not_change_the_image() {
+    let w = World::default();
+    let mut c = Camera::new(21, 13, std::f64::consts::PI / 2.0);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+    c.sampler = Sampler::Stratified(2);
+    let reference = c.render_frame(w.clone(), 5);
+
+    for (size, order) in [(1, TileOrder::Scanline), (4, TileOrder::Spiral), (8, TileOrder::Hilbert), (64, TileOrder::Spiral)] {
+      c.tile_size = size;
+      c.tile_order = order;
+
+      assert_eq!(c.render_frame(w.clone(), 5).sums, reference.sums);
+    }
+  }
+
+  fn small_camera() -> Camera {
+    let mut c = Camera::new(16, 16, std::f64::consts::PI / 2.0);
+    c.tile_size = 4;
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+
+    c
+  }
+
+  #[test]
+  fn observer_sees_every_tile_finish() {
+    let mut c = small_camera();
+    c.sampler = Sampler::Grid(2);
+    let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let seen = reports.clone();
+
+    let mut options = RenderOptions::new();
+    options.observer = Some(Box::new(move |p| seen.lock().unwrap().push(*p)));
+    let frame = c.render_with(World::default(), 5, &options);
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 16);
+    assert_eq!(frame.passes, 1);
+    assert!(reports.iter().any(|p| p.tiles_done == 16 && p.rays_traced == 16 * 16 * 4));
+    assert!(reports.iter().all(|p| p.tiles_total == 16));
+  }
+
+  #[test]
+  fn cancelling_returns_the_partial_frame() {
+    let c = small_camera();
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+
+    let mut options = RenderOptions::new();
+    options.cancellation = Some(token);
+    options.observer = Some(Box::new(move |p| {
+      if p.tiles_done >= 2 {
+        canceller.cancel();
+      }
+    }));
+    // On a single thread no other tile can start before the cancellation
+    // is seen
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let frame = pool.install(|| c.render_with(World::default(), 5, &options));
+
+    assert_eq!(frame.passes, 0);
+    assert_eq!(frame.completed_tiles.len(), 2);
+    assert!(frame.sample_counts.contains(&0));
+  }
+
+  #[test]
+  fn a_spent_time_budget_renders_nothing() {
+    let c = small_camera();
+    let mut options = RenderOptions::new();
+    options.time_budget = Some(std::time::Duration::from_secs(0));
+
+    let frame = c.render_with(World::default(), 5, &options);
+
+    assert!(frame.completed_tiles.is_empty());
+    assert!(frame.sample_counts.iter().all(|&n| n == 0));
+  }
+
+  // A tile per pixel, so that passes can stop and resume part way through
+  fn pixel_tiled_camera() -> Camera {
+    let mut c = small_camera();
+    c.tile_size = 1;
+
+    c
+  }
+
+  #[test]
+  fn progressive_passes_converge_on_the_supersampled_image() {
+    let mut c = small_camera();
+    c.sampler = Sampler::Stratified(2);
+    let mut snapshots = Vec::new();
+
+    let frame = c.render_progressive(World::default(), 5, Some(4), &RenderOptions::new(), |f| snapshots.push(f.passes));
+
+    assert_eq!(snapshots, vec![1, 2, 3, 4]);
+    assert_eq!(frame.samples_at(8, 8), 16);
+
+    c.sampler = Sampler::Stratified(4);
+    let reference = c.render_frame(World::default(), 5);
+    let mean = |f: &Frame| (0..256).map(|i| f.pixel_at(i % 16, i / 16).luminance()).sum::<f64>() / 256.0;
+    assert!((mean(&frame) - mean(&reference)).abs() < 0.005);
+  }
+
+  #[test]
+  fn an_interrupted_pass_resumes_where_it_stopped() {
+    let mut c = pixel_tiled_camera();
+    c.sampler = Sampler::Random(2);
+    let w = World::default();
+    let uninterrupted = c.render_frame(w.clone(), 5);
+
+    let mut frame = Frame::new(16, 16);
+    let mut options = RenderOptions::new();
+    options.time_budget = Some(std::time::Duration::from_secs(0));
+    assert!(!c.render_pass(&w, 5, &mut frame, &options, Instant::now()));
+
+    let tiles = crate::tile::tiles(16, 16, 1, c.tile_order);
+    frame.completed_tiles.extend_from_slice(&tiles[..100]);
+    let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let mut options = RenderOptions::new();
+    let record = reports.clone();
+    options.observer = Some(Box::new(move |p| record.lock().unwrap().push(*p)));
+    assert!(c.render_pass(&w, 5, &mut frame, &options, Instant::now()));
+
+    // Progress covers only the tiles this run renders
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 156);
+    assert!(reports.iter().all(|p| p.tiles_total == 156 && p.tiles_done <= 156));
+
+    assert_eq!(frame.passes, 1);
+    let last = tiles[255];
+    assert_eq!(frame.pixel_at(last.x, last.y), uninterrupted.pixel_at(last.x, last.y));
+    assert_eq!(frame.samples_at(tiles[0].x, tiles[0].y), 0);
+  }
+
+  #[test]
+  fn eta_is_estimated_from_the_current_pass_alone() {
+    let c = small_camera();
+    let pass_ended = std::sync::Arc::new(Mutex::new(Instant::now()));
+    let reports = std::sync::Arc::new(Mutex::new(Vec::new()));
+
+    let mut options = RenderOptions::new();
+    let (ended, record) = (pass_ended.clone(), reports.clone());
+    options.observer = Some(Box::new(move |p| {
+      if p.pass == 1 {
+        record.lock().unwrap().push((*p, ended.lock().unwrap().elapsed()));
+      }
+    }));
+    c.render_progressive(World::default(), 5, Some(2), &options, |_| *pass_ended.lock().unwrap() = Instant::now());
+
+    // Pass 1 started after pass 0 was handed out, so none of its reports can
+    // include time spent on pass 0
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 16);
+    for (p, since_pass_0) in reports.iter() {
+      assert!(p.elapsed <= *since_pass_0);
+
+      let left = (p.tiles_total - p.tiles_done) as f64 / p.tiles_done as f64;
+      assert!(p.eta().unwrap() <= since_pass_0.mul_f64(left));
+    }
+  }
+
+  #[test]
+  fn rendering_a_region_only_traces_pixels_inside_it() {
+    let mut c = small_camera();
+    let full = c.render(World::default(), 5);
+
+    c.region = Some(Tile {
+      x: 5,
+      y: 6,
+      width: 6,
+      height: 3,
+    });
+    let frame = c.render_frame(World::default(), 5);
+    let cropped = c.render_region(World::default(), 5);
+
+    assert_eq!(frame.sample_counts.iter().sum::<u32>(), 18);
+    assert_eq!(frame.samples_at(4, 6), 0);
+    assert_eq!((cropped.width(), cropped.height()), (6, 3));
+    assert_eq!(cropped.get_pixel(2, 1), full.get_pixel(7, 7));
+  }
+
+  #[test]
+  fn a_region_can_be_composited_into_an_existing_canvas() {
+    let mut c = small_camera();
+    let mut canvas = Canvas::new(16, 16);
+    let red = Color { r: 1.0, g: 0.0, b: 0.0 };
+    canvas.set_pixel(0, 0, red);
+    canvas.set_pixel(7, 7, red);
+
+    c.region = Some(Tile {
+      x: 6,
+      y: 6,
+      width: 20,
+      height: 4,
+    });
+    c.render_into(World::default(), 5, &mut canvas);
+
+    assert_eq!(c.region().width, 10);
+    assert_eq!(canvas.get_pixel(0, 0), red);
+    assert_eq!(canvas.get_pixel(7, 7), small_camera().render(World::default(), 5).get_pixel(7, 7));
+  }
+
+  #[test]
+  fn debugging_a_pixel_gives_its_ray_tree() {
+    let c = small_camera();
+    let w = World::default();
+
+    let tree = c.debug_pixel(&w, 8, 8, 5);
+
+    assert_eq!(tree.ray.direction, c.ray_for_pixel(8, 8, 0.5, 0.5).direction);
+    assert_eq!(tree.color, c.render_frame(w, 5).pixel_at(8, 8));
+    assert!(tree.hit.is_some());
+  }
+
+  #[test]
+  fn debugging_a_pixel_uses_the_pixels_random_numbers() {
+    let c = small_camera();
+    let mut w = World::default();
+    w.fog = Some(crate::medium::Medium::new(0.02, 0.05));
+
+    let tree = c.debug_pixel(&w, 3, 8, 5);
+
+    assert_eq!(tree.color, c.render_frame(w, 5).pixel_at(3, 8));
+  }
+
+  #[test]
+  fn debugged_colours_match_the_rendered_ones() {
+    let c = small_camera();
+    let mut w = World::default();
+    w.fog = Some(crate::medium::Medium::new(0.02, 0.05));
+    if let Object::Sphere(ref mut s) = w.objects[0] {
+      s.material.reflective = 0.9;
+      s.material.transparency = 0.9;
+      s.material.refractive_index = 1.5;
+    }
+    if let Object::Sphere(ref mut s) = w.objects[1] {
+      s.holdout = true;
+    }
+    let mut holdout = crate::sphere::Sphere::new();
+    holdout.transform = Matrix::translate(-3.0, 0.0, 0.0);
+    holdout.holdout = true;
+    w.objects.push(Object::Sphere(holdout));
+    let mut floor = crate::plane::Plane::new();
+    floor.transform = Matrix::translate(0.0, -1.0, 0.0);
+    floor.material.reflective = 0.5;
+    w.objects.push(Object::Plane(floor));
+    let aovs = crate::aov::AovSet::new(&w, &[]);
+
+    for y in 0..16 {
+      for x in 0..16 {
+        let tree = c.debug_pixel(&w, x, y, 5);
+        rng::seed(c.pixel_seed(0, x, y));
+        let (sum, _, weight, _) = c.color_for_pixel(&w, x, y, 5, &aovs);
+
+        assert_eq!(tree.color, sum.color * (1.0 / weight), "pixel {}, {}", x, y);
+      }
+    }
+  }
+
+  #[test]
+  fn alpha_tracks_coverage_including_antialiased_edges() {
+    let mut c = small_camera();
+    c.sampler = Sampler::Grid(4);
+
+    let canvas = c.render(World::default(), 5);
+
+    assert_eq!(canvas.get_alpha(0, 0), 0.0);
+    assert_eq!(canvas.get_alpha(8, 8), 1.0);
+    assert!((0..16).any(|x| canvas.get_alpha(x, 8) > 0.0 && canvas.get_alpha(x, 8) < 1.0));
+  }
+
+  #[test]
+  fn aovs_are_rendered_in_the_same_pass() {
+    let c = small_camera();
+    let mut options = RenderOptions::new();
+    options.aovs = vec![Aov::Depth, Aov::ObjectId];
+
+    let frame = c.render_with(World::default(), 5, &options);
+
+    assert_eq!(frame.aovs_at(0, 0), vec![0.0, 0.0]);
+    assert!((frame.aovs_at(8, 8)[0] - 4.0).abs() < 0.1);
+    assert_eq!(frame.aovs_at(8, 8)[1], 1.0);
+    assert_eq!(frame.canvas(), c.render(World::default(), 5));
+  }
+
+  #[test]
+  fn rendering_aovs_leaves_a_stochastic_image_alone() {
+    let c = small_camera();
+    let mut w = World::default();
+    if let Object::Sphere(ref mut s) = w.objects[0] {
+      s.material.reflective = 0.5;
+      s.material.roughness = 0.3;
+      s.material.glossy_samples = 4;
+    }
+    let mut options = RenderOptions::new();
+    options.aovs = vec![Aov::Indirect, Aov::Light(0)];
+
+    let frame = c.render_with(w.clone(), 5, &options);
+
+    assert_eq!(frame.canvas(), c.render(w, 5));
+  }
 }
@@ -1,11 +1,15 @@
 extern crate image;
 
 use std::path::Path;
-use std::env;
+use std::fmt;
+use std::path::PathBuf;
 
-use image::{ImageBuffer, Rgb};
 use std::ops::{Add, Mul, Sub};
 use crate::utils::equal;
+use crate::export::{AlphaMode, ExportOptions};
+use crate::formats::{self, ImageFormat};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
@@ -15,12 +19,65 @@ pub struct Color {
 }
 
 impl Color {
-  fn to_rgb(&self) -> Rgb<u8> {
-    Rgb([
-      (self.r * 255 as f64).min(255.0) as u8,
-      (self.g * 255 as f64).min(255.0) as u8,
-      (self.b * 255 as f64).min(255.0) as u8
-    ])
+  pub fn luminance(&self) -> f64 {
+    0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+  }
+
+  // Colours picked in an image editor or colour picker are sRGB encoded,
+  // the renderer works in linear light.
+  pub fn from_srgb(r: f64, g: f64, b: f64) -> Color {
+    Color {
+      r: srgb_to_linear(r),
+      g: srgb_to_linear(g),
+      b: srgb_to_linear(b),
+    }
+  }
+
+  pub fn from_srgb8(r: u8, g: u8, b: u8) -> Color {
+    Color::from_srgb(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0)
+  }
+
+  pub fn to_srgb(self) -> Color {
+    Color {
+      r: linear_to_srgb(self.r),
+      g: linear_to_srgb(self.g),
+      b: linear_to_srgb(self.b),
+    }
+  }
+
+  // False colour ramp for debug images, from blue at 0 through green to
+  // red at 1.
+  pub fn heat(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0);
+
+    Color { r: t, g: 1.0 - (2.0 * t - 1.0).abs(), b: 1.0 - t }
+  }
+
+  // Clamped and rounded to 8 bits per channel. Until PPM output came along
+  // this truncated, which left every PNG up to one level darker than the
+  // book's rounded PPM values.
+  fn to_rgb(self) -> [u8; 3] {
+    [
+      (self.r * 255.0).round().clamp(0.0, 255.0) as u8,
+      (self.g * 255.0).round().clamp(0.0, 255.0) as u8,
+      (self.b * 255.0).round().clamp(0.0, 255.0) as u8,
+    ]
+  }
+}
+
+pub fn srgb_to_linear(v: f64) -> f64 {
+  if v <= 0.04045 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+pub fn linear_to_srgb(v: f64) -> f64 {
+  if v <= 0.0031308 {
+    v * 12.92
+  } else {
+    1.055 * v.powf(1.0 / 2.4) - 0.055
   }
 }
 
@@ -79,43 +136,166 @@ impl Mul<f64> for Color {
 }
 
 #[derive(Debug)]
+pub enum SaveError {
+  Io(io::Error),
+  // The extension doesn't name a format we can write
+  UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for SaveError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SaveError::Io(e) => write!(f, "{}", e),
+      SaveError::UnknownFormat(path) => write!(f, "don't know which image format to write {} as", path.display()),
+    }
+  }
+}
+
+impl From<io::Error> for SaveError {
+  fn from(e: io::Error) -> SaveError {
+    SaveError::Io(e)
+  }
+}
+
+// Linear, unclamped colour for every pixel, stored row by row. Values only
+// get clamped and quantised to 8 bits when the canvas is saved. Colour is
+// premultiplied by the pixel's alpha, which starts out opaque.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Canvas {
-  buffer: ImageBuffer<Rgb<u8>, Vec<u8>>
+  width: u32,
+  height: u32,
+  pixels: Vec<Color>,
+  alphas: Vec<f64>,
 }
 
 impl Canvas {
   pub fn new(width: u32, height: u32) -> Canvas {
-    let buf = ImageBuffer::new(width, height);
-
     Canvas {
-      buffer: buf
+      width,
+      height,
+      pixels: vec![Color { r: 0.0, g: 0.0, b: 0.0 }; (width * height) as usize],
+      alphas: vec![1.0; (width * height) as usize],
     }
   }
 
   pub fn set_pixel(&mut self, x: u32, y: u32, c: Color) {
-    self.buffer.put_pixel(x, y, c.to_rgb());
+    let i = self.index(x, y);
+    self.pixels[i] = c;
   }
 
   pub fn get_pixel(&self, x: u32, y: u32) -> Color {
-    let pixel = self.buffer.get_pixel(x, y);
+    self.pixels[self.index(x, y)]
+  }
 
-    Color {
-      r: (pixel[0] as f64 / 255.0),
-      g: (pixel[1] as f64 / 255.0),
-      b: (pixel[2] as f64 / 255.0),
+  pub fn set_alpha(&mut self, x: u32, y: u32, alpha: f64) {
+    let i = self.index(x, y);
+    self.alphas[i] = alpha;
+  }
+
+  pub fn get_alpha(&self, x: u32, y: u32) -> f64 {
+    self.alphas[self.index(x, y)]
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  fn index(&self, x: u32, y: u32) -> usize {
+    assert!(x < self.width && y < self.height, "pixel ({}, {}) is outside the {}x{} canvas", x, y, self.width, self.height);
+
+    (y * self.width + x) as usize
+  }
+
+  // Copies all of `other` into this canvas with its top left corner at (x, y).
+  pub fn blit(&mut self, other: &Canvas, x: u32, y: u32) {
+    for oy in 0..other.height {
+      for ox in 0..other.width {
+        self.set_pixel(x + ox, y + oy, other.get_pixel(ox, oy));
+        self.set_alpha(x + ox, y + oy, other.get_alpha(ox, oy));
+      }
     }
   }
 
-  pub fn save(&self, path: &str) {
-    let base = env::current_dir().unwrap();
-    let target = base.join("images").join(path);
-    self.buffer.save(target).unwrap();
+  fn to_rgb8(&self, options: &ExportOptions) -> Vec<u8> {
+    self.pixels.iter().flat_map(|c| options.apply(*c).to_rgb()).collect()
+  }
+
+  fn to_rgba8(&self, options: &ExportOptions) -> Vec<u8> {
+    self
+      .pixels
+      .iter()
+      .zip(&self.alphas)
+      .flat_map(|(c, alpha)| {
+        let (c, alpha) = options.apply_with_alpha(*c, *alpha);
+        let [r, g, b] = c.to_rgb();
+
+        [r, g, b, (alpha * 255.0).round().clamp(0.0, 255.0) as u8]
+      })
+      .collect()
+  }
+
+  // Plain P3 PPM of the raw, clamped values, like the book's tests expect.
+  #[cfg(test)]
+  pub fn to_ppm_string(&self) -> String {
+    let mut out = Vec::new();
+    formats::write_ppm_ascii(self.width, self.height, &self.to_rgb8(&ExportOptions::raw()), &mut out).unwrap();
+
+    String::from_utf8(out).unwrap()
+  }
+
+  #[allow(dead_code, reason = "main.rs names the format itself, since .ppm can be either PPM flavour")]
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+    self.save_with(path, &ExportOptions::new())
+  }
+
+  // Picks the format from the file's extension.
+  pub fn save_with(&self, path: impl AsRef<Path>, options: &ExportOptions) -> Result<(), SaveError> {
+    let path = path.as_ref();
+
+    match ImageFormat::from_extension(path) {
+      Some(format) => self.save_as(path, format, options),
+      None => Err(SaveError::UnknownFormat(path.to_path_buf())),
+    }
+  }
+
+  pub fn save_as(&self, path: impl AsRef<Path>, format: ImageFormat, options: &ExportOptions) -> Result<(), SaveError> {
+    let mut sink = BufWriter::new(File::create(path)?);
+
+    self.write_to(&mut sink, format, options)?;
+    sink.flush()?;
+
+    Ok(())
+  }
+
+  // PNG and EXR get an alpha channel unless `options.alpha` is opaque, the
+  // other formats are always composited over black.
+  pub fn write_to(&self, sink: &mut impl Write, format: ImageFormat, options: &ExportOptions) -> Result<(), SaveError> {
+    let exposure = options.exposure_scale();
+
+    let written = match format {
+      ImageFormat::Png if options.alpha != AlphaMode::Opaque => formats::write_png_rgba(self.width, self.height, &self.to_rgba8(options), sink),
+      ImageFormat::Png => formats::write_png(self.width, self.height, &self.to_rgb8(options), sink),
+      ImageFormat::PpmAscii => formats::write_ppm_ascii(self.width, self.height, &self.to_rgb8(options), sink),
+      ImageFormat::PpmBinary => formats::write_ppm_binary(self.width, self.height, &self.to_rgb8(options), sink),
+      ImageFormat::Pfm => formats::write_pfm(self, sink, |c| c * exposure),
+      ImageFormat::Exr => formats::write_exr(self, sink, options),
+    };
+
+    Ok(written?)
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::canvas::{Canvas, Color};
+  use crate::canvas::{linear_to_srgb, srgb_to_linear, Canvas, Color, SaveError};
+  use crate::formats::ImageFormat;
+  use crate::export::ExportOptions;
+  use crate::tone_map::ToneMap;
+  use crate::utils::equal;
 
   #[test]
   fn can_set_and_get_pixel() {
@@ -154,4 +334,152 @@ mod tests {
 
     assert_eq!(c3 * 2.0, Color { r: 2.0, g: 4.0, b: 6.0 });
   }
+
+  #[test]
+  fn luminance_of_white_is_one() {
+    let white = Color { r: 1.0, g: 1.0, b: 1.0 };
+
+    assert!(equal(white.luminance(), 1.0));
+  }
+
+  #[test]
+  fn pixels_keep_values_outside_the_displayable_range() {
+    let mut c = Canvas::new(2, 1);
+    let bright = Color { r: 4.5, g: 0.123456789, b: -0.25 };
+
+    c.set_pixel(1, 0, bright);
+
+    assert_eq!(c.get_pixel(1, 0).r, 4.5);
+    assert_eq!(c.get_pixel(1, 0).g, 0.123456789);
+    assert_eq!(c.get_pixel(1, 0).b, -0.25);
+  }
+
+  #[test]
+  fn export_clamps_and_quantises() {
+    let mut c = Canvas::new(1, 1);
+    c.set_pixel(0, 0, Color { r: 4.5, g: 0.5, b: -0.25 });
+
+    assert_eq!(c.to_rgb8(&ExportOptions::raw()), vec![255, 128, 0]);
+  }
+
+  #[test]
+  fn quantising_rounds_to_the_nearest_level() {
+    let mut c = Canvas::new(2, 1);
+    c.set_pixel(0, 0, Color { r: 127.4 / 255.0, g: 127.6 / 255.0, b: 254.5 / 255.0 });
+    c.set_pixel(1, 0, Color { r: 0.4 / 255.0, g: 0.6 / 255.0, b: 1.0 });
+
+    assert_eq!(c.to_rgb8(&ExportOptions::raw()), vec![127, 128, 255, 0, 1, 255]);
+  }
+
+  #[test]
+  fn tone_mapping_is_chosen_per_export() {
+    let mut c = Canvas::new(1, 1);
+    c.set_pixel(0, 0, Color { r: 1.0, g: 1.0, b: 1.0 });
+    let mut options = ExportOptions::raw();
+    options.tone_map = ToneMap::Reinhard;
+
+    assert_eq!(c.to_rgb8(&options), vec![128, 128, 128]);
+    assert_eq!(c.to_rgb8(&ExportOptions::raw()), vec![255, 255, 255]);
+  }
+
+  #[test]
+  fn export_encodes_linear_midtones_as_srgb() {
+    let mut c = Canvas::new(1, 1);
+    c.set_pixel(0, 0, Color { r: 0.5, g: 0.5, b: 0.5 });
+
+    assert_eq!(c.to_rgb8(&ExportOptions::new()), vec![188, 188, 188]);
+  }
+
+  #[test]
+  fn ppm_header_and_pixel_data() {
+    let mut c = Canvas::new(5, 3);
+    c.set_pixel(0, 0, Color { r: 1.5, g: 0.0, b: 0.0 });
+    c.set_pixel(2, 1, Color { r: 0.0, g: 0.5, b: 0.0 });
+    c.set_pixel(4, 2, Color { r: -0.5, g: 0.0, b: 1.0 });
+
+    assert_eq!(
+      c.to_ppm_string(),
+      "P3\n5 3\n255\n\
+       255 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n\
+       0 0 0 0 0 0 0 128 0 0 0 0 0 0 0\n\
+       0 0 0 0 0 0 0 0 0 0 0 0 0 0 255\n"
+    );
+  }
+
+  #[test]
+  fn long_ppm_lines_are_split() {
+    let mut c = Canvas::new(10, 2);
+    for y in 0..2 {
+      for x in 0..10 {
+        c.set_pixel(x, y, Color { r: 1.0, g: 0.8, b: 0.6 });
+      }
+    }
+
+    assert_eq!(
+      c.to_ppm_string(),
+      "P3\n10 2\n255\n\
+       255 204 153 255 204 153 255 204 153 255 204 153 255 204 153 255 204\n\
+       153 255 204 153 255 204 153 255 204 153 255 204 153\n\
+       255 204 153 255 204 153 255 204 153 255 204 153 255 204 153 255 204\n\
+       153 255 204 153 255 204 153 255 204 153 255 204 153\n"
+    );
+  }
+
+  #[test]
+  fn ppm_files_end_with_a_newline() {
+    assert!(Canvas::new(5, 3).to_ppm_string().ends_with('\n'));
+  }
+
+  #[test]
+  fn srgb_round_trips() {
+    for v in [0.0, 0.002, 0.04, 0.2, 0.5, 0.9, 1.0] {
+      assert!(equal(srgb_to_linear(linear_to_srgb(v)), v));
+    }
+
+    assert_eq!(Color::from_srgb8(255, 255, 255), Color { r: 1.0, g: 1.0, b: 1.0 });
+    assert_eq!(Color::from_srgb8(188, 188, 188).to_srgb(), Color { r: 188.0 / 255.0, g: 188.0 / 255.0, b: 188.0 / 255.0 });
+    assert!(equal(Color::from_srgb(0.5, 0.5, 0.5).r, 0.21404114));
+  }
+
+  #[test]
+  fn images_can_be_written_to_any_sink() {
+    let mut c = Canvas::new(1, 1);
+    c.set_pixel(0, 0, Color { r: 1.0, g: 0.0, b: 0.5 });
+    let mut out = Vec::new();
+
+    c.write_to(&mut out, ImageFormat::PpmAscii, &ExportOptions::raw()).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "P3\n1 1\n255\n255 0 128\n");
+  }
+
+  #[test]
+  fn saving_to_an_absolute_path() {
+    let path = std::env::temp_dir().join(format!("raytracer-canvas-{}.ppm", std::process::id()));
+
+    Canvas::new(2, 2).save(&path).unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"P6\n2 2\n255\n\0\0\0\0\0\0\0\0\0\0\0\0");
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn saving_reports_errors_instead_of_panicking() {
+    let c = Canvas::new(1, 1);
+
+    assert!(matches!(c.save("/nonexistent-directory/image.png"), Err(SaveError::Io(_))));
+    assert!(matches!(c.save("image.unknown"), Err(SaveError::UnknownFormat(_))));
+  }
+
+  #[test]
+  fn blitting_copies_a_canvas_into_another() {
+    let mut small = Canvas::new(2, 2);
+    let red = Color { r: 1.0, g: 0.0, b: 0.0 };
+    small.set_pixel(1, 1, red);
+
+    let mut big = Canvas::new(4, 4);
+    big.blit(&small, 2, 1);
+
+    assert_eq!(big.get_pixel(3, 2), red);
+    assert_eq!(big.get_pixel(1, 1), Color { r: 0.0, g: 0.0, b: 0.0 });
+  }
 }
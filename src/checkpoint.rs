@@ -0,0 +1,353 @@
+use crate::aov::Aov;
+use crate::camera::Camera;
+use crate::canvas::Color;
+use crate::frame::{Frame, Sample};
+use crate::tile::Tile;
+use crate::world::World;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Checkpoints store a frame so a render can be picked up again after the
+// process dies. All numbers are little endian:
+//
+//   "RTCK", version: u32, scene hash: u64
+//   width: u32, height: u32, passes: u32
+//   completed tile count: u32, then x, y, width, height: u32 per tile
+//   AOV count: u32, then name length: u32 and UTF-8 name per AOV
+//   premultiplied r, g, b, alpha, weight: f64 and sample count: u32 per pixel, row by row
+//   the AOV sums: f64, row by row
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 4;
+// Longer than any AOV name, so a corrupt length fails before allocating
+const MAX_NAME_LENGTH: u32 = 64;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+  Io(io::Error),
+  Format(String),
+  // The checkpoint was written for a different camera or world
+  SceneMismatch,
+}
+
+impl fmt::Display for CheckpointError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CheckpointError::Io(e) => write!(f, "{}", e),
+      CheckpointError::Format(message) => write!(f, "not a valid checkpoint: {}", message),
+      CheckpointError::SceneMismatch => write!(f, "checkpoint was made from a different scene"),
+    }
+  }
+}
+
+impl From<io::Error> for CheckpointError {
+  fn from(e: io::Error) -> CheckpointError {
+    match e.kind() {
+      io::ErrorKind::UnexpectedEof => CheckpointError::Format(String::from("file is truncated")),
+      _ => CheckpointError::Io(e),
+    }
+  }
+}
+
+// FNV-1a over the debug representation of everything that decides what a
+// pass adds to the frame: the camera, the world, the recursion depth and the
+// AOVs. Any change, down to the last float, gives a different hash.
+pub fn scene_hash(camera: &Camera, world: &World, max_steps: u8, aovs: &[Aov]) -> u64 {
+  format!("{:?}{:?}{:?}{:?}", camera, world, max_steps, aovs)
+    .bytes()
+    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+pub fn write(frame: &Frame, scene_hash: u64, sink: &mut impl Write) -> io::Result<()> {
+  sink.write_all(MAGIC)?;
+  sink.write_all(&VERSION.to_le_bytes())?;
+  sink.write_all(&scene_hash.to_le_bytes())?;
+
+  sink.write_all(&frame.width.to_le_bytes())?;
+  sink.write_all(&frame.height.to_le_bytes())?;
+  sink.write_all(&frame.passes.to_le_bytes())?;
+
+  sink.write_all(&(frame.completed_tiles.len() as u32).to_le_bytes())?;
+  for tile in &frame.completed_tiles {
+    for n in [tile.x, tile.y, tile.width, tile.height] {
+      sink.write_all(&n.to_le_bytes())?;
+    }
+  }
+
+  sink.write_all(&(frame.aovs.len() as u32).to_le_bytes())?;
+  for aov in &frame.aovs {
+    let name = aov.name();
+    sink.write_all(&(name.len() as u32).to_le_bytes())?;
+    sink.write_all(name.as_bytes())?;
+  }
+
+  for i in 0..frame.sums.len() {
+    let sum = frame.sums[i];
+
+    for n in [sum.color.r, sum.color.g, sum.color.b, sum.alpha, frame.weights[i]] {
+      sink.write_all(&n.to_le_bytes())?;
+    }
+    sink.write_all(&frame.sample_counts[i].to_le_bytes())?;
+  }
+
+  for v in &frame.aov_sums {
+    sink.write_all(&v.to_le_bytes())?;
+  }
+
+  Ok(())
+}
+
+// Reads a checkpoint made for `camera`, whose size the frame must have.
+pub fn read(source: &mut impl Read, camera: &Camera, scene_hash: u64) -> Result<Frame, CheckpointError> {
+  let mut magic = [0; 4];
+  source.read_exact(&mut magic)?;
+  if &magic != MAGIC {
+    return Err(CheckpointError::Format(String::from("bad magic number")));
+  }
+
+  let version = read_u32(source)?;
+  if version != VERSION {
+    return Err(CheckpointError::Format(format!("unsupported version {}", version)));
+  }
+
+  if read_u64(source)? != scene_hash {
+    return Err(CheckpointError::SceneMismatch);
+  }
+
+  let width = read_u32(source)?;
+  let height = read_u32(source)?;
+  if (width, height) != (camera.hsize, camera.vsize) {
+    return Err(invalid_data(format!("frame is {}x{}, not {}x{}", width, height, camera.hsize, camera.vsize)));
+  }
+  let passes = read_u32(source)?;
+
+  let mut completed_tiles = Vec::new();
+  let tiles = read_u32(source)?;
+  for _ in 0..tiles {
+    completed_tiles.push(Tile {
+      x: read_u32(source)?,
+      y: read_u32(source)?,
+      width: read_u32(source)?,
+      height: read_u32(source)?,
+    });
+  }
+
+  let mut aovs = Vec::new();
+  for _ in 0..read_u32(source)? {
+    let length = read_u32(source)?;
+    if length > MAX_NAME_LENGTH {
+      return Err(invalid_data(format!("AOV name is {} bytes long", length)));
+    }
+    let mut name = vec![0; length as usize];
+    source.read_exact(&mut name)?;
+
+    let name = String::from_utf8_lossy(&name);
+    aovs.push(Aov::from_name(&name).ok_or_else(|| CheckpointError::Format(format!("unknown AOV {}", name)))?);
+  }
+
+  let mut frame = Frame::with_aovs(width, height, &aovs);
+  frame.passes = passes;
+  frame.completed_tiles = completed_tiles;
+
+  for i in 0..frame.sums.len() {
+    frame.sums[i] = Sample {
+      color: Color {
+        r: read_f64(source)?,
+        g: read_f64(source)?,
+        b: read_f64(source)?,
+      },
+      alpha: read_f64(source)?,
+    };
+    frame.weights[i] = read_f64(source)?;
+    frame.sample_counts[i] = read_u32(source)?;
+  }
+
+  for v in frame.aov_sums.iter_mut() {
+    *v = read_f64(source)?;
+  }
+
+  Ok(frame)
+}
+
+pub fn save(frame: &Frame, scene_hash: u64, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+  // Write next to the old checkpoint and swap it in, so dying half way
+  // through a write never loses the previous one.
+  let path = path.as_ref();
+  let partial = path.with_extension("partial");
+
+  let mut sink = BufWriter::new(File::create(&partial)?);
+  write(frame, scene_hash, &mut sink)?;
+  sink.flush()?;
+  drop(sink);
+
+  std::fs::rename(partial, path)?;
+
+  Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>, camera: &Camera, scene_hash: u64) -> Result<Frame, CheckpointError> {
+  read(&mut BufReader::new(File::open(path)?), camera, scene_hash)
+}
+
+fn invalid_data(message: String) -> CheckpointError {
+  CheckpointError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn read_u32(source: &mut impl Read) -> io::Result<u32> {
+  let mut bytes = [0; 4];
+  source.read_exact(&mut bytes)?;
+
+  Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(source: &mut impl Read) -> io::Result<u64> {
+  let mut bytes = [0; 8];
+  source.read_exact(&mut bytes)?;
+
+  Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(source: &mut impl Read) -> io::Result<f64> {
+  Ok(f64::from_bits(read_u64(source)?))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::aov::Aov;
+  use crate::camera::Camera;
+  use crate::checkpoint::{read, scene_hash, write, CheckpointError};
+  use crate::frame::Frame;
+  use crate::point::Point;
+  use crate::progress::{CancellationToken, RenderOptions};
+  use crate::sampler::Sampler;
+  use crate::vector::Vector;
+  use crate::world::World;
+  use std::io;
+
+  fn camera() -> Camera {
+    let mut c = Camera::new(12, 8, std::f64::consts::PI / 2.0);
+    c.tile_size = 1;
+    c.sampler = Sampler::Random(2);
+    c.transform = Camera::view_transform(
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: -5.0,
+      },
+      Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+      },
+      Vector {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+      },
+    );
+
+    c
+  }
+
+  fn round_trip(frame: &Frame, hash: u64) -> Frame {
+    let mut bytes = Vec::new();
+    write(frame, hash, &mut bytes).unwrap();
+
+    read(&mut bytes.as_slice(), &camera(), hash).unwrap()
+  }
+
+  #[test]
+  fn resuming_from_a_checkpoint_gives_the_same_image() {
+    let c = camera();
+    let w = World::default();
+    let hash = scene_hash(&c, &w, 5, &[]);
+    let uninterrupted = c.render_progressive(w.clone(), 5, Some(3), &RenderOptions::new(), |_| {});
+
+    // Cancel part of the way through the second pass
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    let mut options = RenderOptions::new();
+    options.cancellation = Some(token);
+    options.observer = Some(Box::new(move |p| {
+      if p.pass == 1 && p.tiles_done >= 10 {
+        canceller.cancel();
+      }
+    }));
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let frame = pool.install(|| c.render_progressive(w.clone(), 5, Some(3), &options, |_| {}));
+    assert_eq!(frame.completed_tiles.len(), 10);
+
+    let restored = round_trip(&frame, hash);
+    assert_eq!(restored.passes, 1);
+    assert_eq!(restored.completed_tiles, frame.completed_tiles);
+
+    let resumed = c.resume_progressive(w, restored, 5, Some(3), &RenderOptions::new(), |_| {});
+
+    assert_eq!(resumed.sums, uninterrupted.sums);
+    assert_eq!(resumed.sample_counts, uninterrupted.sample_counts);
+  }
+
+  #[test]
+  fn aovs_are_kept_in_checkpoints() {
+    let mut options = RenderOptions::new();
+    options.aovs = vec![Aov::Normal, Aov::Light(0)];
+    let frame = camera().render_with(World::default(), 5, &options);
+
+    let restored = round_trip(&frame, 1);
+
+    assert_eq!(restored.aovs, frame.aovs);
+    assert_eq!(restored.aov_sums, frame.aov_sums);
+  }
+
+  #[test]
+  fn a_different_scene_is_rejected() {
+    let c = camera();
+    let w = World::default();
+    let mut bytes = Vec::new();
+    write(&Frame::new(12, 8), scene_hash(&c, &w, 5, &[]), &mut bytes).unwrap();
+
+    let mut moved = c;
+    moved.transform = moved.transform * crate::matrix::Matrix::translate(0.0, 0.0, 0.001);
+
+    assert!(matches!(read(&mut bytes.as_slice(), &c, scene_hash(&moved, &w, 5, &[])), Err(CheckpointError::SceneMismatch)));
+    assert!(matches!(read(&mut bytes.as_slice(), &c, scene_hash(&c, &w, 4, &[])), Err(CheckpointError::SceneMismatch)));
+    assert!(matches!(read(&mut bytes.as_slice(), &c, scene_hash(&c, &w, 5, &[Aov::Depth])), Err(CheckpointError::SceneMismatch)));
+    assert!(read(&mut bytes.as_slice(), &c, scene_hash(&c, &w, 5, &[])).is_ok());
+  }
+
+  #[test]
+  fn truncated_checkpoints_are_rejected() {
+    let mut bytes = Vec::new();
+    write(&Frame::new(12, 8), 1, &mut bytes).unwrap();
+    bytes.truncate(bytes.len() - 1);
+
+    assert!(matches!(read(&mut bytes.as_slice(), &camera(), 1), Err(CheckpointError::Format(_))));
+    assert!(matches!(read(&mut &b"nope"[..], &camera(), 1), Err(CheckpointError::Format(_))));
+  }
+
+  #[test]
+  fn corrupted_headers_are_rejected() {
+    let invalid = |bytes: &[u8]| match read(&mut &bytes[..], &camera(), 1) {
+      Err(CheckpointError::Io(e)) => e.kind() == io::ErrorKind::InvalidData,
+      _ => false,
+    };
+    let mut bytes = Vec::new();
+    write(&Frame::with_aovs(12, 8, &[Aov::Depth]), 1, &mut bytes).unwrap();
+
+    // Width
+    let mut wrong_size = bytes.clone();
+    wrong_size[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(invalid(&wrong_size));
+
+    // Length of the first AOV name, after the header, no tiles and the AOV count
+    let mut long_name = bytes.clone();
+    long_name[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(invalid(&long_name));
+
+    // A frame of a different size is just as wrong for this camera
+    let mut other_size = Vec::new();
+    write(&Frame::new(8, 12), 1, &mut other_size).unwrap();
+    assert!(invalid(&other_size));
+  }
+}
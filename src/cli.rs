@@ -0,0 +1,180 @@
+use crate::aov::Aov;
+use crate::export::AlphaMode;
+use crate::formats::ImageFormat;
+use crate::post::PostProcess;
+use crate::utils::number;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: raytracer [--progressive] [--passes N] [--snapshot-every N | Ns] [--time-budget SECONDS] [--checkpoint PATH] [--format png | ppm | ppm-ascii | pfm | exr] [--alpha straight | premultiplied] [--aovs NAME,...] [--denoise] [--post SPEC] [--debug-pixel X,Y]";
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum SnapshotInterval {
+  Passes(u32),
+  Seconds(f64),
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Args {
+  pub progressive: bool,
+  // Passes to render in progressive mode, None keeps going until the time
+  // budget runs out or the process is killed.
+  pub passes: Option<u32>,
+  pub snapshot_every: Option<SnapshotInterval>,
+  pub time_budget: Option<Duration>,
+  // Saved after every pass and resumed from if it already exists
+  pub checkpoint: Option<PathBuf>,
+  // What the image is saved as, PNG if None
+  pub format: Option<ImageFormat>,
+  // Save with an alpha channel instead of over black
+  pub alpha: Option<AlphaMode>,
+  // Written to a layered EXR next to the image
+  pub aovs: Vec<Aov>,
+  // Filter the finished image, guided by the albedo, normal and depth AOVs
+  pub denoise: bool,
+  // Effects run on the finished image, see `PostProcess::parse`
+  pub post: PostProcess,
+  // Print the ray tree of this pixel instead of rendering
+  pub debug_pixel: Option<(u32, u32)>,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+  let mut parsed = Args::default();
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+    match arg.as_str() {
+      "--progressive" => parsed.progressive = true,
+      "--denoise" => parsed.denoise = true,
+      "--passes" => {
+        parsed.passes = Some(number(&value()?)?);
+        parsed.progressive = true;
+      }
+      "--snapshot-every" => {
+        let v = value()?;
+        parsed.snapshot_every = Some(match v.strip_suffix('s') {
+          Some(seconds) => SnapshotInterval::Seconds(number(seconds)?),
+          None => SnapshotInterval::Passes(number(&v)?),
+        });
+        parsed.progressive = true;
+      }
+      "--checkpoint" => {
+        parsed.checkpoint = Some(PathBuf::from(value()?));
+        parsed.progressive = true;
+      }
+      "--format" => {
+        let v = value()?;
+        parsed.format = Some(ImageFormat::from_name(&v).ok_or(format!("{} is not an image format", v))?);
+      }
+      "--alpha" => {
+        parsed.alpha = Some(match value()?.as_str() {
+          "straight" => AlphaMode::Straight,
+          "premultiplied" => AlphaMode::Premultiplied,
+          v => return Err(format!("{} is not an alpha mode", v)),
+        })
+      }
+      "--aovs" => {
+        parsed.aovs = value()?
+          .split(',')
+          .map(|name| Aov::from_name(name).ok_or(format!("unknown AOV {}", name)))
+          .collect::<Result<_, _>>()?
+      }
+      "--post" => parsed.post = PostProcess::parse(&value()?)?,
+      "--debug-pixel" => {
+        let v = value()?;
+        let (x, y) = v.split_once(',').ok_or(format!("{} is not a pixel, use X,Y", v))?;
+        parsed.debug_pixel = Some((number(x)?, number(y)?));
+      }
+      "--time-budget" => parsed.time_budget = Some(Duration::from_secs_f64(number(&value()?)?)),
+      _ => return Err(format!("unknown argument {}", arg)),
+    }
+  }
+
+  Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::aov::Aov;
+  use crate::cli::{parse, Args, SnapshotInterval};
+  use crate::export::AlphaMode;
+  use crate::formats::ImageFormat;
+  use crate::post::{Effect, PostProcess};
+  use std::time::Duration;
+
+  fn args(s: &str) -> Result<Args, String> {
+    parse(s.split_whitespace().map(String::from))
+  }
+
+  #[test]
+  fn no_arguments_renders_a_single_pass() {
+    assert_eq!(args(""), Ok(Args::default()));
+  }
+
+  #[test]
+  fn snapshot_intervals_are_passes_or_seconds() {
+    let a = args("--snapshot-every 4").unwrap();
+    assert_eq!(a.snapshot_every, Some(SnapshotInterval::Passes(4)));
+    assert!(a.progressive);
+
+    let a = args("--snapshot-every 2.5s --passes 16 --time-budget 60").unwrap();
+    assert_eq!(a.snapshot_every, Some(SnapshotInterval::Seconds(2.5)));
+    assert_eq!(a.passes, Some(16));
+    assert_eq!(a.time_budget, Some(Duration::from_secs(60)));
+  }
+
+  #[test]
+  fn checkpoints_make_the_render_progressive() {
+    let a = args("--checkpoint render.ckpt").unwrap();
+
+    assert_eq!(a.checkpoint, Some(std::path::PathBuf::from("render.ckpt")));
+    assert!(a.progressive);
+  }
+
+  #[test]
+  fn formats_are_picked_by_name() {
+    assert_eq!(args("--format ppm-ascii").unwrap().format, Some(ImageFormat::PpmAscii));
+    assert_eq!(args("--format tiff"), Err(String::from("tiff is not an image format")));
+  }
+
+  #[test]
+  fn alpha_can_be_straight_or_premultiplied() {
+    assert_eq!(args("--alpha straight").unwrap().alpha, Some(AlphaMode::Straight));
+    assert_eq!(args("--alpha premultiplied").unwrap().alpha, Some(AlphaMode::Premultiplied));
+    assert_eq!(args("--alpha none"), Err(String::from("none is not an alpha mode")));
+  }
+
+  #[test]
+  fn aovs_are_a_comma_separated_list() {
+    assert_eq!(args("--aovs depth,normal,light1").unwrap().aovs, vec![Aov::Depth, Aov::Normal, Aov::Light(1)]);
+    assert_eq!(args("--aovs depth,fog"), Err(String::from("unknown AOV fog")));
+  }
+
+  #[test]
+  fn denoising_is_off_unless_asked_for() {
+    assert!(!args("").unwrap().denoise);
+    assert!(args("--denoise --passes 4").unwrap().denoise);
+  }
+
+  #[test]
+  fn post_processing_is_read_from_a_spec() {
+    assert_eq!(args("").unwrap().post, PostProcess::new());
+    assert_eq!(args("--post vignette").unwrap().post, PostProcess::new().with(Effect::Vignette { strength: 0.4 }));
+    assert_eq!(args("--post blur"), Err(String::from("unknown effect blur")));
+  }
+
+  #[test]
+  fn debug_pixels_are_a_pair_of_coordinates() {
+    assert_eq!(args("--debug-pixel 120,45").unwrap().debug_pixel, Some((120, 45)));
+    assert_eq!(args("--debug-pixel 120"), Err(String::from("120 is not a pixel, use X,Y")));
+  }
+
+  #[test]
+  fn bad_arguments_are_reported() {
+    assert_eq!(args("--passes"), Err(String::from("--passes needs a value")));
+    assert_eq!(args("--passes x"), Err(String::from("x is not a valid number")));
+    assert_eq!(args("--fast"), Err(String::from("unknown argument --fast")));
+  }
+}
@@ -10,8 +10,13 @@ use crate::utils::EPSILON;
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Cube {
   pub transform: Matrix,
+  // Where the shape has moved to when the shutter closes, if it moves.
+  pub end_transform: Option<Matrix>,
   pub material: Material,
   pub casts_shadow: bool,
+  // Hidden from the camera and cut out of the alpha channel, but still
+  // blocks and shadows everything else. Mirrors and glass see it as black.
+  pub holdout: bool,
 }
 
 impl Intersectable for Cube {
@@ -35,7 +40,7 @@ impl Intersectable for Cube {
   }
 
   fn intersect(&self, r: Ray) -> Vec<f64> {
-    let ray2 = r * self.transform.inverse().unwrap();
+    let ray2 = r * self.transform_at(r.time).inverse().unwrap();
 
     let [xtmin, xtmax] = Cube::check_axis(ray2.origin.x, ray2.direction.x);
     let [ytmin, ytmax] = Cube::check_axis(ray2.origin.y, ray2.direction.y);
@@ -59,17 +64,30 @@ impl Intersectable for Cube {
     self.transform
   }
 
+  fn transform_at(&self, time: f64) -> Matrix {
+    match self.end_transform {
+      Some(end) => self.transform.interpolate(&end, time),
+      None => self.transform,
+    }
+  }
+
   fn casts_shadow(&self) -> bool {
     self.casts_shadow
   }
+
+  fn holdout(&self) -> bool {
+    self.holdout
+  }
 }
 
 impl Cube {
   pub fn new() -> Cube {
     Cube {
       transform: Matrix::identity(),
+      end_transform: None,
       material: Material::new(),
       casts_shadow: true,
+      holdout: false,
     }
   }
 
@@ -115,7 +133,7 @@ mod tests {
 
   // #[test]
   // fn insersects_sphere_at_two_points() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let sphere = Sphere::new();
 
   //   let intersections = sphere.intersect(ray);
@@ -127,7 +145,7 @@ mod tests {
 
   // #[test]
   // fn insersects_sphere_at_tangent() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 1.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 1.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let sphere = Sphere::new();
 
   //   let intersections = sphere.intersect(ray);
@@ -139,7 +157,7 @@ mod tests {
 
   // #[test]
   // fn ray_originates_inside_sphere() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let sphere = Sphere::new();
 
   //   let intersections = sphere.intersect(ray);
@@ -151,7 +169,7 @@ mod tests {
 
   // #[test]
   // fn ray_is_behind_sphere() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let sphere = Sphere::new();
 
   //   let intersections = sphere.intersect(ray);
@@ -163,7 +181,7 @@ mod tests {
 
   // // #[test]
   // // fn intersect_sets_the_object_on_intersection() {
-  // //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  // //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   // //   let sphere = Sphere::new();
 
   // //   let intersections = sphere.intersect(ray);
@@ -193,7 +211,7 @@ mod tests {
 
   // #[test]
   // fn intersecting_a_scaled_sphere_with_a_ray() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let mut sphere = Sphere::new();
   //   sphere.set_transform(Matrix::scale(2.0, 2.0, 2.0));
 
@@ -205,7 +223,7 @@ mod tests {
 
   // #[test]
   // fn intersecting_a_translated_sphere_with_a_ray() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let mut sphere = Sphere::new();
   //   sphere.set_transform(Matrix::translate(5.0, 0.0, 0.0));
 
@@ -0,0 +1,253 @@
+use crate::aov::Aov;
+use crate::canvas::{Canvas, Color};
+use crate::frame::Frame;
+use crate::utils::EPSILON;
+use rayon::prelude::*;
+
+// B3 spline, the smoothing kernel of every à-trous level
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). Every
+// iteration blurs with the same 5x5 kernel spread twice as far apart, and
+// stops at edges in the image or in any of the guides. Sigmas are the
+// differences at which a neighbour's weight drops to 1/e; the colour sigma
+// halves every iteration so later, wider passes only smooth what is left.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Denoiser {
+  pub iterations: u32,
+  pub color_sigma: f64,
+  pub normal_sigma: f64,
+  // Relative to the depth of the pixel being filtered
+  pub depth_sigma: f64,
+  pub albedo_sigma: f64,
+}
+
+// Feature buffers that tell the denoiser where the edges are. Any of them
+// can be missing.
+#[derive(Debug, Clone, Default)]
+pub struct Guides {
+  pub albedo: Option<Canvas>,
+  pub normal: Option<Canvas>,
+  // Depth in every channel
+  pub depth: Option<Canvas>,
+}
+
+impl Guides {
+  // Whichever of the albedo, normal and depth AOVs the frame has.
+  pub fn from_frame(frame: &Frame) -> Guides {
+    Guides {
+      albedo: frame.aov_canvas(Aov::Albedo),
+      normal: frame.aov_canvas(Aov::Normal),
+      depth: frame.aov_canvas(Aov::Depth),
+    }
+  }
+}
+
+impl Denoiser {
+  pub fn new() -> Denoiser {
+    Denoiser {
+      iterations: 5,
+      color_sigma: 0.5,
+      normal_sigma: 0.3,
+      depth_sigma: 0.05,
+      albedo_sigma: 0.1,
+    }
+  }
+
+  // The AOVs a frame needs for `Guides::from_frame` to find every guide.
+  pub fn aovs() -> Vec<Aov> {
+    vec![Aov::Albedo, Aov::Normal, Aov::Depth]
+  }
+
+  // Filters the HDR canvas and keeps its alpha. With an albedo guide the
+  // lighting is filtered on its own and multiplied back onto the albedo, so
+  // textures stay sharp.
+  pub fn apply(&self, canvas: &Canvas, guides: &Guides) -> Canvas {
+    let (width, height) = (canvas.width(), canvas.height());
+    let albedo_at = |x, y| guides.albedo.as_ref().map(|a| a.get_pixel(x, y));
+
+    let mut current = Canvas::new(width, height);
+    for y in 0..height {
+      for x in 0..width {
+        let c = canvas.get_pixel(x, y);
+        current.set_pixel(x, y, albedo_at(x, y).map_or(c, |a| demodulate(c, a)));
+      }
+    }
+
+    for iteration in 0..self.iterations {
+      let step = 1 << iteration;
+      let color_sigma = self.color_sigma / (1 << iteration) as f64;
+      let source = &current;
+
+      let filtered: Vec<Color> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| (0..width).map(move |x| self.filter_pixel(source, guides, x, y, step, color_sigma)))
+        .collect();
+
+      for (i, c) in filtered.into_iter().enumerate() {
+        current.set_pixel(i as u32 % width, i as u32 / width, c);
+      }
+    }
+
+    let mut out = canvas.clone();
+    for y in 0..height {
+      for x in 0..width {
+        let c = current.get_pixel(x, y);
+        out.set_pixel(x, y, albedo_at(x, y).map_or(c, |a| remodulate(c, a)));
+      }
+    }
+
+    out
+  }
+
+  fn filter_pixel(&self, source: &Canvas, guides: &Guides, x: u32, y: u32, step: i64, color_sigma: f64) -> Color {
+    let (width, height) = (source.width(), source.height());
+    let center = source.get_pixel(x, y);
+    let mut sum = Color { r: 0.0, g: 0.0, b: 0.0 };
+    let mut total_weight = 0.0;
+
+    for (j, ky) in KERNEL.iter().enumerate() {
+      for (i, kx) in KERNEL.iter().enumerate() {
+        let qx = x as i64 + (i as i64 - 2) * step;
+        let qy = y as i64 + (j as i64 - 2) * step;
+        if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+          continue;
+        }
+
+        let (qx, qy) = (qx as u32, qy as u32);
+        let c = source.get_pixel(qx, qy);
+
+        let mut exponent = distance_squared(center, c) / (color_sigma * color_sigma);
+        if let Some(n) = &guides.normal {
+          exponent += distance_squared(n.get_pixel(x, y), n.get_pixel(qx, qy)) / (self.normal_sigma * self.normal_sigma);
+        }
+        if let Some(a) = &guides.albedo {
+          exponent += distance_squared(a.get_pixel(x, y), a.get_pixel(qx, qy)) / (self.albedo_sigma * self.albedo_sigma);
+        }
+        if let Some(d) = &guides.depth {
+          let depth = d.get_pixel(x, y).r;
+          let difference = (depth - d.get_pixel(qx, qy).r) / (self.depth_sigma * depth.abs().max(EPSILON));
+          exponent += difference * difference;
+        }
+
+        let weight = kx * ky * (-exponent).exp();
+        sum = sum + c * weight;
+        total_weight += weight;
+      }
+    }
+
+    // The centre pixel always has a weight of at least 9/64
+    sum * (1.0 / total_weight)
+  }
+}
+
+fn distance_squared(a: Color, b: Color) -> f64 {
+  let d = a - b;
+
+  d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+// Divides out the albedo, leaving channels with no albedo as they are.
+fn demodulate(c: Color, albedo: Color) -> Color {
+  let divide = |v: f64, a: f64| if a > EPSILON { v / a } else { v };
+
+  Color {
+    r: divide(c.r, albedo.r),
+    g: divide(c.g, albedo.g),
+    b: divide(c.b, albedo.b),
+  }
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+  let multiply = |v: f64, a: f64| if a > EPSILON { v * a } else { v };
+
+  Color {
+    r: multiply(c.r, albedo.r),
+    g: multiply(c.g, albedo.g),
+    b: multiply(c.b, albedo.b),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::{Canvas, Color};
+  use crate::denoise::{Denoiser, Guides};
+  use crate::rng::Rng;
+
+  fn noisy(width: u32, height: u32, base: impl Fn(u32, u32) -> f64) -> Canvas {
+    let mut rng = Rng::new(7);
+    let mut c = Canvas::new(width, height);
+
+    for y in 0..height {
+      for x in 0..width {
+        let v = base(x, y) + (rng.next_f64() - 0.5) * 0.2;
+        c.set_pixel(x, y, Color { r: v, g: v, b: v });
+      }
+    }
+
+    c
+  }
+
+  fn variance(c: &Canvas, xs: std::ops::Range<u32>) -> f64 {
+    let values: Vec<f64> = (0..c.height()).flat_map(|y| xs.clone().map(move |x| (x, y))).map(|(x, y)| c.get_pixel(x, y).r).collect();
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+  }
+
+  #[test]
+  fn noise_on_a_flat_surface_is_smoothed_away() {
+    let c = noisy(32, 32, |_, _| 0.5);
+
+    let denoised = Denoiser::new().apply(&c, &Guides::default());
+
+    assert!(variance(&denoised, 0..32) < variance(&c, 0..32) / 10.0);
+  }
+
+  #[test]
+  fn edges_in_the_guides_are_kept() {
+    // Two walls at right angles whose colours differ by less than the
+    // noise, so only the normals tell them apart
+    let c = noisy(32, 8, |x, _| if x < 16 { 0.2 } else { 0.3 });
+    let mut normal = Canvas::new(32, 8);
+    for y in 0..8 {
+      for x in 0..32 {
+        normal.set_pixel(x, y, if x < 16 { Color { r: 1.0, g: 0.0, b: 0.0 } } else { Color { r: 0.0, g: 0.0, b: -1.0 } });
+      }
+    }
+    let guides = Guides {
+      normal: Some(normal),
+      ..Guides::default()
+    };
+
+    let denoised = Denoiser::new().apply(&c, &guides);
+    let unguided = Denoiser::new().apply(&c, &Guides::default());
+
+    let mean = |c: &Canvas, x: u32| (0..8).map(|y| c.get_pixel(x, y).r).sum::<f64>() / 8.0;
+    assert!((mean(&denoised, 15) - 0.2).abs() < 0.02);
+    assert!((mean(&denoised, 16) - 0.3).abs() < 0.02);
+    assert!(mean(&unguided, 16) - mean(&unguided, 15) < mean(&denoised, 16) - mean(&denoised, 15));
+  }
+
+  #[test]
+  fn textures_survive_through_the_albedo() {
+    let mut c = Canvas::new(8, 8);
+    let mut albedo = Canvas::new(8, 8);
+    for y in 0..8 {
+      for x in 0..8 {
+        let a = if (x + y) % 2 == 0 { 0.2 } else { 0.8 };
+        albedo.set_pixel(x, y, Color { r: a, g: a, b: a });
+        c.set_pixel(x, y, Color { r: a * 0.5, g: a * 0.5, b: a * 0.5 });
+      }
+    }
+    c.set_alpha(3, 3, 0.25);
+    let guides = Guides {
+      albedo: Some(albedo),
+      ..Guides::default()
+    };
+
+    let denoised = Denoiser::new().apply(&c, &guides);
+
+    assert_eq!(denoised, c);
+  }
+}
@@ -0,0 +1,203 @@
+use crate::canvas::{Canvas, Color};
+
+// Side of the square windows SSIM compares
+const SSIM_WINDOW: u32 = 7;
+
+// Ways of telling how far apart two renders of the same size are. Colour
+// differences are measured on the linear values, SSIM on the luminance of
+// the clamped, sRGB encoded values that end up in an image file.
+impl Canvas {
+  // Per channel |self - other|.
+  pub fn abs_diff(&self, other: &Canvas) -> Canvas {
+    self.assert_same_size(other);
+    let mut diff = Canvas::new(self.width(), self.height());
+
+    for y in 0..self.height() {
+      for x in 0..self.width() {
+        let d = self.get_pixel(x, y) - other.get_pixel(x, y);
+
+        diff.set_pixel(x, y, Color { r: d.r.abs(), g: d.g.abs(), b: d.b.abs() });
+      }
+    }
+
+    diff
+  }
+
+  // Root mean square difference over every channel of every pixel.
+  pub fn rmse(&self, other: &Canvas) -> f64 {
+    self.assert_same_size(other);
+    let mut sum = 0.0;
+
+    for y in 0..self.height() {
+      for x in 0..self.width() {
+        let d = self.get_pixel(x, y) - other.get_pixel(x, y);
+        sum += d.r * d.r + d.g * d.g + d.b * d.b;
+      }
+    }
+
+    (sum / (self.width() * self.height() * 3).max(1) as f64).sqrt()
+  }
+
+  // Peak signal to noise ratio in dB, for values that peak at 1. Identical
+  // images give infinity.
+  pub fn psnr(&self, other: &Canvas) -> f64 {
+    let rmse = self.rmse(other);
+
+    if rmse == 0.0 {
+      return f64::INFINITY;
+    }
+
+    -20.0 * rmse.log10()
+  }
+
+  // Mean structural similarity (Wang et al. 2004) over every 7x7 window, or
+  // over the whole image if it is smaller than that. 1 means identical.
+  pub fn ssim(&self, other: &Canvas) -> f64 {
+    self.assert_same_size(other);
+    let (c1, c2) = (0.01 * 0.01, 0.03 * 0.03);
+    let luma = |c: &Canvas, x, y| c.get_pixel(x, y).to_srgb().luminance().clamp(0.0, 1.0);
+
+    let window_width = SSIM_WINDOW.min(self.width());
+    let window_height = SSIM_WINDOW.min(self.height());
+    let n = (window_width * window_height) as f64;
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for wy in 0..=self.height() - window_height {
+      for wx in 0..=self.width() - window_width {
+        let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+        for y in wy..wy + window_height {
+          for x in wx..wx + window_width {
+            let (a, b) = (luma(self, x, y), luma(other, x, y));
+            sa += a;
+            sb += b;
+            saa += a * a;
+            sbb += b * b;
+            sab += a * b;
+          }
+        }
+
+        let (mean_a, mean_b) = (sa / n, sb / n);
+        let variance_a = saa / n - mean_a * mean_a;
+        let variance_b = sbb / n - mean_b * mean_b;
+        let covariance = sab / n - mean_a * mean_b;
+
+        total += ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+          / ((mean_a * mean_a + mean_b * mean_b + c1) * (variance_a + variance_b + c2));
+        windows += 1;
+      }
+    }
+
+    total / windows.max(1) as f64
+  }
+
+  // Where two images differ, from blue for no difference to red for a
+  // difference of `max` or more in any channel.
+  pub fn diff_heatmap(&self, other: &Canvas, max: f64) -> Canvas {
+    let diff = self.abs_diff(other);
+    let mut heatmap = Canvas::new(self.width(), self.height());
+
+    for y in 0..self.height() {
+      for x in 0..self.width() {
+        let d = diff.get_pixel(x, y);
+
+        heatmap.set_pixel(x, y, Color::heat(d.r.max(d.g).max(d.b) / max));
+      }
+    }
+
+    heatmap
+  }
+
+  fn assert_same_size(&self, other: &Canvas) {
+    assert!(
+      self.width() == other.width() && self.height() == other.height(),
+      "can't compare a {}x{} canvas with a {}x{} one",
+      self.width(),
+      self.height(),
+      other.width(),
+      other.height()
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::{Canvas, Color};
+  use crate::rng::Rng;
+  use crate::utils::equal;
+
+  fn gradient(width: u32, height: u32) -> Canvas {
+    let mut c = Canvas::new(width, height);
+
+    for y in 0..height {
+      for x in 0..width {
+        let v = (x + y) as f64 / (width + height) as f64;
+        c.set_pixel(x, y, Color { r: v, g: v * 0.5, b: 1.0 - v });
+      }
+    }
+
+    c
+  }
+
+  #[test]
+  fn identical_images_have_no_difference() {
+    let c = gradient(16, 16);
+
+    assert_eq!(c.abs_diff(&c), Canvas::new(16, 16));
+    assert_eq!(c.rmse(&c), 0.0);
+    assert_eq!(c.psnr(&c), f64::INFINITY);
+    assert!(equal(c.ssim(&c), 1.0));
+  }
+
+  #[test]
+  fn rmse_and_psnr_of_a_uniform_offset() {
+    let a = Canvas::new(4, 4);
+    let mut b = Canvas::new(4, 4);
+    for y in 0..4 {
+      for x in 0..4 {
+        b.set_pixel(x, y, Color { r: 0.1, g: -0.1, b: 0.1 });
+      }
+    }
+
+    assert!(equal(a.rmse(&b), 0.1));
+    assert!(equal(a.psnr(&b), 20.0));
+    assert_eq!(a.abs_diff(&b).get_pixel(2, 3), Color { r: 0.1, g: 0.1, b: 0.1 });
+  }
+
+  #[test]
+  fn ssim_drops_with_noise_more_than_with_a_brightness_shift() {
+    let c = gradient(32, 32);
+    let mut noisy = c.clone();
+    let mut brighter = c.clone();
+    let mut rng = Rng::new(1);
+    for y in 0..32 {
+      for x in 0..32 {
+        let p = c.get_pixel(x, y);
+        noisy.set_pixel(x, y, p * (1.0 + (rng.next_f64() - 0.5) * 0.6));
+        brighter.set_pixel(x, y, p * 1.05);
+      }
+    }
+
+    assert!(c.ssim(&noisy) < c.ssim(&brighter));
+    assert!(c.ssim(&brighter) < 1.0);
+  }
+
+  #[test]
+  fn heatmap_marks_where_images_differ() {
+    let a = Canvas::new(2, 1);
+    let mut b = Canvas::new(2, 1);
+    b.set_pixel(1, 0, Color { r: 0.0, g: 0.5, b: 0.0 });
+
+    let heatmap = a.diff_heatmap(&b, 0.25);
+
+    assert_eq!(heatmap.get_pixel(0, 0), Color { r: 0.0, g: 0.0, b: 1.0 });
+    assert_eq!(heatmap.get_pixel(1, 0), Color { r: 1.0, g: 0.0, b: 0.0 });
+  }
+
+  #[test]
+  #[should_panic]
+  fn images_of_different_sizes_cannot_be_compared() {
+    Canvas::new(2, 2).rmse(&Canvas::new(2, 3));
+  }
+}
@@ -0,0 +1,137 @@
+use crate::canvas::Color;
+use crate::tone_map::ToneMap;
+use crate::utils::EPSILON;
+
+// What happens to a canvas's coverage when an image is saved. Canvas colour
+// is always premultiplied by alpha.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum AlphaMode {
+  // No alpha channel, the image is composited over black
+  Opaque,
+  // Colour divided back out by alpha, which is what PNG viewers expect
+  Straight,
+  // Colour left multiplied by alpha, which is what compositors expect
+  Premultiplied,
+}
+
+// How linear canvas colour is turned into displayable values when an image
+// is saved.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ExportOptions {
+  // In stops, every stop doubles the brightness
+  pub exposure: f64,
+  pub tone_map: ToneMap,
+  // Encode with the sRGB transfer function, which is what image viewers
+  // expect. Turning it off writes linear values straight to the file.
+  pub srgb: bool,
+  pub alpha: AlphaMode,
+}
+
+impl ExportOptions {
+  pub fn new() -> ExportOptions {
+    ExportOptions {
+      exposure: 0.0,
+      tone_map: ToneMap::Clamp,
+      srgb: true,
+      alpha: AlphaMode::Opaque,
+    }
+  }
+
+  // Linear values clamped straight into the file, like images were written
+  // before sRGB encoding.
+  #[allow(dead_code, reason = "only tests compare against raw values so far")]
+  pub fn raw() -> ExportOptions {
+    ExportOptions {
+      srgb: false,
+      ..ExportOptions::new()
+    }
+  }
+
+  pub fn exposure_scale(&self) -> f64 {
+    2.0_f64.powf(self.exposure)
+  }
+
+  pub fn apply(&self, c: Color) -> Color {
+    let mapped = self.tone_map.apply(c * self.exposure_scale());
+
+    if self.srgb {
+      mapped.to_srgb()
+    } else {
+      mapped
+    }
+  }
+
+  // Display values and alpha for a pixel whose colour is premultiplied by
+  // `alpha`. Tone mapping and sRGB encoding only make sense on straight
+  // colour, so it is divided out first.
+  pub fn apply_with_alpha(&self, c: Color, alpha: f64) -> (Color, f64) {
+    match self.alpha {
+      AlphaMode::Opaque => (self.apply(c), 1.0),
+      AlphaMode::Straight => (self.apply(unpremultiply(c, alpha)), alpha),
+      AlphaMode::Premultiplied => (self.apply(unpremultiply(c, alpha)) * alpha, alpha),
+    }
+  }
+
+  // Like `apply_with_alpha` for float formats, which keep linear values
+  // and only get the exposure.
+  pub fn linear_with_alpha(&self, c: Color, alpha: f64) -> (Color, f64) {
+    let c = c * self.exposure_scale();
+
+    match self.alpha {
+      AlphaMode::Opaque => (c, 1.0),
+      AlphaMode::Straight => (unpremultiply(c, alpha), alpha),
+      AlphaMode::Premultiplied => (c, alpha),
+    }
+  }
+}
+
+fn unpremultiply(c: Color, alpha: f64) -> Color {
+  if alpha < EPSILON {
+    return c;
+  }
+
+  c * (1.0 / alpha)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::Color;
+  use crate::export::{AlphaMode, ExportOptions};
+
+  #[test]
+  fn each_stop_of_exposure_doubles_the_brightness() {
+    let mut options = ExportOptions::raw();
+    options.exposure = 2.0;
+
+    assert_eq!(options.apply(Color { r: 0.1, g: 0.2, b: 0.05 }), Color { r: 0.4, g: 0.8, b: 0.2 });
+
+    options.exposure = -1.0;
+    assert_eq!(options.apply(Color { r: 0.1, g: 0.2, b: 0.05 }), Color { r: 0.05, g: 0.1, b: 0.025 });
+  }
+
+  #[test]
+  fn straight_alpha_divides_out_the_coverage_before_encoding() {
+    let mut options = ExportOptions::raw();
+    options.alpha = AlphaMode::Straight;
+    let half_red = Color { r: 0.4, g: 0.0, b: 0.0 };
+
+    assert_eq!(options.apply_with_alpha(half_red, 0.5), (Color { r: 0.8, g: 0.0, b: 0.0 }, 0.5));
+
+    options.alpha = AlphaMode::Premultiplied;
+    assert_eq!(options.apply_with_alpha(half_red, 0.5), (half_red, 0.5));
+
+    options.alpha = AlphaMode::Opaque;
+    assert_eq!(options.apply_with_alpha(half_red, 0.5), (half_red, 1.0));
+  }
+
+  #[test]
+  fn premultiplied_alpha_is_encoded_before_multiplying() {
+    let mut options = ExportOptions::new();
+    options.alpha = AlphaMode::Premultiplied;
+
+    let (c, alpha) = options.apply_with_alpha(Color { r: 0.1, g: 0.1, b: 0.1 }, 0.5);
+
+    assert_eq!(alpha, 0.5);
+    assert_eq!(c, Color { r: 0.2, g: 0.2, b: 0.2 }.to_srgb() * 0.5);
+  }
+}
@@ -0,0 +1,86 @@
+// Reconstruction filter used to weight the samples taken for a pixel. Each
+// pixel spreads its samples over the filter's footprint, centred on the
+// pixel, and averages them by weight.
+#[derive(PartialEq, Debug, Copy, Clone)]
+#[allow(dead_code, reason = "the demo scene in main.rs keeps the default box filter")]
+pub enum Filter {
+  Box,
+  Tent,
+  Gaussian,
+  MitchellNetravali,
+}
+
+impl Filter {
+  // Half the width of the footprint, in pixels
+  pub fn radius(&self) -> f64 {
+    match *self {
+      Filter::Box => 0.5,
+      Filter::Tent => 1.0,
+      Filter::Gaussian => 1.5,
+      Filter::MitchellNetravali => 2.0,
+    }
+  }
+
+  pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+    self.weight_1d(dx) * self.weight_1d(dy)
+  }
+
+  fn weight_1d(&self, d: f64) -> f64 {
+    let x = d.abs();
+
+    if x > self.radius() {
+      return 0.0;
+    }
+
+    match *self {
+      Filter::Box => 1.0,
+      Filter::Tent => 1.0 - x,
+      Filter::Gaussian => {
+        let alpha = 2.0;
+        (-alpha * x * x).exp() - (-alpha * self.radius() * self.radius()).exp()
+      }
+      Filter::MitchellNetravali => {
+        let b = 1.0 / 3.0;
+        let c = 1.0 / 3.0;
+
+        if x < 1.0 {
+          ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)) / 6.0
+        } else {
+          ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x.powi(2) + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::filter::Filter;
+  use crate::utils::equal;
+
+  #[test]
+  fn box_filter_weighs_everything_in_the_pixel_equally() {
+    assert_eq!(Filter::Box.weight(0.0, 0.0), 1.0);
+    assert_eq!(Filter::Box.weight(0.4, -0.4), 1.0);
+    assert_eq!(Filter::Box.weight(0.6, 0.0), 0.0);
+  }
+
+  #[test]
+  fn tent_filter_falls_off_linearly() {
+    assert!(equal(Filter::Tent.weight(0.5, 0.0), 0.5));
+    assert!(equal(Filter::Tent.weight(0.5, 0.5), 0.25));
+  }
+
+  #[test]
+  fn filters_vanish_at_their_radius() {
+    for f in [Filter::Tent, Filter::Gaussian, Filter::MitchellNetravali] {
+      assert!(equal(f.weight(f.radius(), 0.0), 0.0));
+      assert!(f.weight(0.0, 0.0) > 0.0);
+    }
+  }
+
+  #[test]
+  fn mitchell_netravali_has_negative_lobes() {
+    assert!(Filter::MitchellNetravali.weight(1.5, 0.0) < 0.0);
+  }
+}
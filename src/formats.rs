@@ -0,0 +1,355 @@
+use crate::canvas::{Canvas, Color};
+use crate::export::{AlphaMode, ExportOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum ImageFormat {
+  Png,
+  // Plain text PPM, as used by the book
+  PpmAscii,
+  // Binary PPM
+  PpmBinary,
+  // Portable float map, linear 32-bit floats
+  Pfm,
+  // Uncompressed scanline OpenEXR, linear 32-bit floats
+  Exr,
+}
+
+impl ImageFormat {
+  // Binary PPM for .ppm, since it's what most tools write
+  pub fn from_extension(path: impl AsRef<Path>) -> Option<ImageFormat> {
+    let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+    match extension.as_str() {
+      "png" => Some(ImageFormat::Png),
+      "ppm" => Some(ImageFormat::PpmBinary),
+      "pfm" => Some(ImageFormat::Pfm),
+      "exr" => Some(ImageFormat::Exr),
+      _ => None,
+    }
+  }
+
+  // For picking a format by name, which is the only way to get plain text
+  // PPM since it shares its extension with binary PPM.
+  pub fn from_name(name: &str) -> Option<ImageFormat> {
+    match name {
+      "png" => Some(ImageFormat::Png),
+      "ppm" => Some(ImageFormat::PpmBinary),
+      "ppm-ascii" => Some(ImageFormat::PpmAscii),
+      "pfm" => Some(ImageFormat::Pfm),
+      "exr" => Some(ImageFormat::Exr),
+      _ => None,
+    }
+  }
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      ImageFormat::Png => "png",
+      ImageFormat::PpmAscii | ImageFormat::PpmBinary => "ppm",
+      ImageFormat::Pfm => "pfm",
+      ImageFormat::Exr => "exr",
+    }
+  }
+}
+
+// 8-bit values, three per pixel, row by row.
+pub fn write_png(width: u32, height: u32, rgb: &[u8], sink: &mut impl Write) -> io::Result<()> {
+  image::png::PNGEncoder::new(sink).encode(rgb, width, height, image::ColorType::RGB(8))
+}
+
+// Like `write_png` with a fourth, alpha value per pixel.
+pub fn write_png_rgba(width: u32, height: u32, rgba: &[u8], sink: &mut impl Write) -> io::Result<()> {
+  image::png::PNGEncoder::new(sink).encode(rgba, width, height, image::ColorType::RGBA(8))
+}
+
+// P3 writes one number per channel, wrapping lines before 70 characters.
+pub fn write_ppm_ascii(width: u32, height: u32, rgb: &[u8], sink: &mut impl Write) -> io::Result<()> {
+  write!(sink, "P3\n{} {}\n255\n", width, height)?;
+
+  for row in rgb.chunks(width.max(1) as usize * 3) {
+    let mut line = String::new();
+
+    for value in row {
+      let value = value.to_string();
+
+      if !line.is_empty() && line.len() + 1 + value.len() > 70 {
+        writeln!(sink, "{}", line)?;
+        line.clear();
+      }
+      if !line.is_empty() {
+        line.push(' ');
+      }
+      line.push_str(&value);
+    }
+
+    writeln!(sink, "{}", line)?;
+  }
+
+  Ok(())
+}
+
+pub fn write_ppm_binary(width: u32, height: u32, rgb: &[u8], sink: &mut impl Write) -> io::Result<()> {
+  write!(sink, "P6\n{} {}\n255\n", width, height)?;
+  sink.write_all(rgb)
+}
+
+// Rows go bottom to top, and a negative scale means little endian.
+pub fn write_pfm(canvas: &Canvas, sink: &mut impl Write, map: impl Fn(Color) -> Color) -> io::Result<()> {
+  write!(sink, "PF\n{} {}\n-1.0\n", canvas.width(), canvas.height())?;
+
+  for y in (0..canvas.height()).rev() {
+    for x in 0..canvas.width() {
+      let c = map(canvas.get_pixel(x, y));
+
+      for v in [c.r, c.g, c.b] {
+        sink.write_all(&(v as f32).to_le_bytes())?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+// Reads back colour PFM files of either byte order. Alpha is opaque.
+#[cfg(test)]
+pub fn read_pfm(source: &mut impl io::Read) -> io::Result<Canvas> {
+  let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("not a colour PFM file: {}", message));
+
+  let mut bytes = Vec::new();
+  source.read_to_end(&mut bytes)?;
+
+  // Four whitespace separated words: PF, width, height and scale
+  let mut words = Vec::new();
+  let mut start = 0;
+  for (i, byte) in bytes.iter().enumerate() {
+    if byte.is_ascii_whitespace() {
+      if i > start {
+        words.push(String::from_utf8_lossy(&bytes[start..i]).into_owned());
+      }
+      start = i + 1;
+
+      if words.len() == 4 {
+        break;
+      }
+    }
+  }
+  if words.len() < 4 || words[0] != "PF" {
+    return Err(invalid("bad header"));
+  }
+
+  let width: u32 = words[1].parse().map_err(|_| invalid("bad width"))?;
+  let height: u32 = words[2].parse().map_err(|_| invalid("bad height"))?;
+  let little_endian = words[3].parse::<f64>().map_err(|_| invalid("bad scale"))? < 0.0;
+
+  let size = (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(3 * 4)).ok_or_else(|| invalid("too large"))?;
+  let data = &bytes[start..];
+  if data.len() < size {
+    return Err(invalid("truncated"));
+  }
+
+  let mut canvas = Canvas::new(width, height);
+  let mut values = data.chunks_exact(4).map(|b| {
+    let b = [b[0], b[1], b[2], b[3]];
+    (if little_endian { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+  });
+  for y in (0..height).rev() {
+    for x in 0..width {
+      let (r, g, b) = (values.next().unwrap(), values.next().unwrap(), values.next().unwrap());
+      canvas.set_pixel(x, y, Color { r, g, b });
+    }
+  }
+
+  Ok(canvas)
+}
+
+// Adds an A channel unless `options.alpha` is opaque.
+pub fn write_exr(canvas: &Canvas, sink: &mut impl Write, options: &ExportOptions) -> io::Result<()> {
+  let mut pixels = Vec::with_capacity((canvas.width() * canvas.height()) as usize);
+  for y in 0..canvas.height() {
+    for x in 0..canvas.width() {
+      pixels.push(options.linear_with_alpha(canvas.get_pixel(x, y), canvas.get_alpha(x, y)));
+    }
+  }
+
+  let mut channels: Vec<(&str, Vec<f32>)> = vec![
+    ("B", pixels.iter().map(|(c, _)| c.b as f32).collect()),
+    ("G", pixels.iter().map(|(c, _)| c.g as f32).collect()),
+    ("R", pixels.iter().map(|(c, _)| c.r as f32).collect()),
+  ];
+  if options.alpha != AlphaMode::Opaque {
+    channels.insert(0, ("A", pixels.iter().map(|(_, alpha)| *alpha as f32).collect()));
+  }
+
+  write_exr_channels(canvas.width(), canvas.height(), &channels, sink)
+}
+
+// Writes any number of named float channels, each holding width * height
+// values row by row. Channel names must be sorted, as OpenEXR requires.
+pub fn write_exr_channels(width: u32, height: u32, channels: &[(impl AsRef<str>, Vec<f32>)], sink: &mut impl Write) -> io::Result<()> {
+  let mut header = Vec::new();
+  header.extend_from_slice(&0x0131_2f76_u32.to_le_bytes());
+  header.extend_from_slice(&2_u32.to_le_bytes());
+
+  let mut channel_list = Vec::new();
+  for (name, _) in channels {
+    channel_list.extend_from_slice(name.as_ref().as_bytes());
+    channel_list.push(0);
+    // FLOAT pixels, not perceptually linear, no subsampling
+    channel_list.extend_from_slice(&2_i32.to_le_bytes());
+    channel_list.extend_from_slice(&[0, 0, 0, 0]);
+    channel_list.extend_from_slice(&1_i32.to_le_bytes());
+    channel_list.extend_from_slice(&1_i32.to_le_bytes());
+  }
+  channel_list.push(0);
+
+  let mut window = Vec::new();
+  for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+    window.extend_from_slice(&v.to_le_bytes());
+  }
+
+  let attributes: [(&str, &str, Vec<u8>); 8] = [
+    ("channels", "chlist", channel_list),
+    ("compression", "compression", vec![0]),
+    ("dataWindow", "box2i", window.clone()),
+    ("displayWindow", "box2i", window),
+    ("lineOrder", "lineOrder", vec![0]),
+    ("pixelAspectRatio", "float", 1.0_f32.to_le_bytes().to_vec()),
+    ("screenWindowCenter", "v2f", [0.0_f32.to_le_bytes(), 0.0_f32.to_le_bytes()].concat()),
+    ("screenWindowWidth", "float", 1.0_f32.to_le_bytes().to_vec()),
+  ];
+  for (name, kind, value) in attributes.iter() {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+  }
+  header.push(0);
+  sink.write_all(&header)?;
+
+  // One scanline per block: its y, its size, then each channel's values
+  let block_size = 8 + 4 * width as u64 * channels.len() as u64;
+  let first_block = header.len() as u64 + 8 * height as u64;
+  for y in 0..height as u64 {
+    sink.write_all(&(first_block + y * block_size).to_le_bytes())?;
+  }
+
+  for y in 0..height as usize {
+    sink.write_all(&(y as i32).to_le_bytes())?;
+    sink.write_all(&((block_size - 8) as i32).to_le_bytes())?;
+
+    for (_, values) in channels {
+      for v in &values[y * width as usize..(y + 1) * width as usize] {
+        sink.write_all(&v.to_le_bytes())?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::{Canvas, Color};
+  use crate::export::{AlphaMode, ExportOptions};
+  use crate::formats::{read_pfm, write_exr, write_pfm, write_ppm_binary, ImageFormat};
+  use std::convert::TryInto;
+
+  #[test]
+  fn formats_are_picked_by_extension() {
+    assert_eq!(ImageFormat::from_extension("render.PNG"), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_extension("a.b/render.exr"), Some(ImageFormat::Exr));
+    assert_eq!(ImageFormat::from_extension("render.pfm"), Some(ImageFormat::Pfm));
+    assert_eq!(ImageFormat::from_extension("render"), None);
+  }
+
+  #[test]
+  fn formats_can_be_picked_by_name() {
+    for format in [ImageFormat::Png, ImageFormat::PpmAscii, ImageFormat::PpmBinary, ImageFormat::Pfm, ImageFormat::Exr] {
+      let name = if format == ImageFormat::PpmAscii { "ppm-ascii" } else { format.extension() };
+
+      assert_eq!(ImageFormat::from_name(name), Some(format));
+    }
+    assert_eq!(ImageFormat::from_name("jpg"), None);
+  }
+
+  #[test]
+  fn binary_ppm_is_a_header_and_raw_bytes() {
+    let mut out = Vec::new();
+    write_ppm_binary(2, 1, &[255, 0, 0, 0, 128, 255], &mut out).unwrap();
+
+    assert_eq!(out, b"P6\n2 1\n255\n\xff\x00\x00\x00\x80\xff");
+  }
+
+  #[test]
+  fn pfm_stores_little_endian_floats_bottom_row_first() {
+    let mut c = Canvas::new(1, 2);
+    c.set_pixel(0, 1, Color { r: 2.5, g: 0.0, b: 0.0 });
+    let mut out = Vec::new();
+    write_pfm(&c, &mut out, |c| c).unwrap();
+
+    assert!(out.starts_with(b"PF\n1 2\n-1.0\n"));
+    assert_eq!(out[12..16], 2.5_f32.to_le_bytes());
+    assert_eq!(out.len(), 12 + 2 * 3 * 4);
+  }
+
+  #[test]
+  fn pfm_files_read_back_in() {
+    let mut c = Canvas::new(3, 2);
+    c.set_pixel(2, 0, Color { r: 2.5, g: -1.0, b: 0.125 });
+    let mut out = Vec::new();
+    write_pfm(&c, &mut out, |c| c).unwrap();
+
+    assert_eq!(read_pfm(&mut out.as_slice()).unwrap(), c);
+    assert!(read_pfm(&mut &out[..out.len() - 1]).is_err());
+    assert!(read_pfm(&mut &b"P6\n1 1\n255\n"[..]).is_err());
+
+    let huge = read_pfm(&mut &b"PF\n4294967295 4294967295\n-1\n"[..]).unwrap_err();
+    assert_eq!(huge.kind(), std::io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn exr_has_a_header_offset_table_and_scanlines() {
+    let mut c = Canvas::new(2, 2);
+    c.set_pixel(1, 1, Color { r: 8.0, g: 0.5, b: 0.25 });
+    let mut out = Vec::new();
+    write_exr(&c, &mut out, &ExportOptions::new()).unwrap();
+
+    assert_eq!(out[0..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    // Two scanline blocks of y, size, then 2 pixels each of B, G and R,
+    // after an offset table pointing at them
+    let block = 8 + 24;
+    let first = out.len() - 2 * block;
+    let offset = |i: usize| u64::from_le_bytes(out[first - 16 + i * 8..first - 8 + i * 8].try_into().unwrap()) as usize;
+    assert_eq!(offset(0), first);
+    assert_eq!(offset(1), first + block);
+
+    let last = first + block;
+    assert_eq!(out[last..last + 4], 1_i32.to_le_bytes());
+    assert_eq!(out[last + 4..last + 8], 24_i32.to_le_bytes());
+    assert_eq!(out[last + 12..last + 16], 0.25_f32.to_le_bytes());
+    assert_eq!(out[last + 20..last + 24], 0.5_f32.to_le_bytes());
+    assert_eq!(out[last + 28..last + 32], 8.0_f32.to_le_bytes());
+  }
+
+  #[test]
+  fn exr_gets_an_alpha_channel_unless_opaque() {
+    let mut c = Canvas::new(1, 1);
+    c.set_pixel(0, 0, Color { r: 0.5, g: 0.25, b: 0.0 });
+    c.set_alpha(0, 0, 0.5);
+    let mut options = ExportOptions::new();
+    options.alpha = AlphaMode::Straight;
+    let mut out = Vec::new();
+    write_exr(&c, &mut out, &options).unwrap();
+
+    // One scanline of y, size, then A, B, G and R
+    let line = out.len() - 24;
+    assert_eq!(out[line + 4..line + 8], 16_i32.to_le_bytes());
+    assert_eq!(out[line + 8..line + 12], 0.5_f32.to_le_bytes());
+    assert_eq!(out[line + 20..line + 24], 1.0_f32.to_le_bytes());
+    assert!(out.windows(2).any(|w| w == b"A\0"));
+  }
+}
@@ -0,0 +1,326 @@
+use crate::aov::{self, Aov};
+use crate::canvas::{Canvas, Color};
+use crate::tile::Tile;
+use crate::utils::EPSILON;
+use std::ops::{Add, Mul};
+
+// What a camera sample sees: the colour, premultiplied by its coverage, and
+// the coverage itself, 0 where the ray escaped or hit a holdout.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Sample {
+  pub color: Color,
+  pub alpha: f64,
+}
+
+impl Sample {
+  pub fn transparent() -> Sample {
+    Sample {
+      color: Color { r: 0.0, g: 0.0, b: 0.0 },
+      alpha: 0.0,
+    }
+  }
+}
+
+impl Add<Sample> for Sample {
+  type Output = Sample;
+
+  fn add(self, other: Sample) -> Sample {
+    Sample {
+      color: self.color + other.color,
+      alpha: self.alpha + other.alpha,
+    }
+  }
+}
+
+impl Mul<f64> for Sample {
+  type Output = Sample;
+
+  fn mul(self, scalar: f64) -> Sample {
+    Sample {
+      color: self.color * scalar,
+      alpha: self.alpha * scalar,
+    }
+  }
+}
+
+// Accumulation buffer for a render: the filter-weighted sum of every sample
+// each pixel has taken, the total filter weight and the number of samples,
+// stored row by row. Progressive renders keep adding passes to the same frame.
+#[derive(Debug)]
+pub struct Frame {
+  pub width: u32,
+  pub height: u32,
+  pub sums: Vec<Sample>,
+  pub weights: Vec<f64>,
+  pub sample_counts: Vec<u32>,
+  // Extra buffers rendered alongside the image, and their weighted sums,
+  // `aov::stride(&aovs)` values per pixel
+  pub aovs: Vec<Aov>,
+  pub aov_sums: Vec<f64>,
+  // Number of finished passes
+  pub passes: u32,
+  // Tiles that are done in the pass currently under way
+  pub completed_tiles: Vec<Tile>,
+}
+
+// The part of one row of a frame's buffers that falls inside a tile.
+pub struct TileRow<'a> {
+  pub sums: &'a mut [Sample],
+  pub weights: &'a mut [f64],
+  pub counts: &'a mut [u32],
+  pub aov_sums: &'a mut [f64],
+}
+
+// The rows of a frame that fall inside one tile. Views from the same frame
+// never overlap, so each render thread can write into its own without locking.
+pub struct TileView<'a> {
+  pub tile: Tile,
+  pub rows: Vec<TileRow<'a>>,
+}
+
+impl Frame {
+  #[allow(dead_code, reason = "main.rs always renders with the AOVs it was asked for")]
+  pub fn new(width: u32, height: u32) -> Frame {
+    Frame::with_aovs(width, height, &[])
+  }
+
+  pub fn with_aovs(width: u32, height: u32, aovs: &[Aov]) -> Frame {
+    let size = (width * height) as usize;
+
+    Frame {
+      width,
+      height,
+      sums: vec![Sample::transparent(); size],
+      weights: vec![0.0; size],
+      sample_counts: vec![0; size],
+      aovs: aovs.to_vec(),
+      aov_sums: vec![0.0; size * aov::stride(aovs)],
+      passes: 0,
+      completed_tiles: Vec::new(),
+    }
+  }
+
+  // Average of the samples taken so far, transparent black if there are none.
+  pub fn sample_at(&self, x: u32, y: u32) -> Sample {
+    let i = (y * self.width + x) as usize;
+
+    if self.weights[i].abs() < EPSILON {
+      return self.sums[i];
+    }
+
+    self.sums[i] * (1.0 / self.weights[i])
+  }
+
+  #[allow(dead_code, reason = "main.rs only reads whole canvases")]
+  pub fn pixel_at(&self, x: u32, y: u32) -> Color {
+    self.sample_at(x, y).color
+  }
+
+  // Fraction of the pixel covered by geometry, antialiased edges included.
+  #[allow(dead_code, reason = "main.rs only reads whole canvases")]
+  pub fn alpha_at(&self, x: u32, y: u32) -> f64 {
+    self.sample_at(x, y).alpha
+  }
+
+  // Average of every AOV at a pixel, one after the other.
+  pub fn aovs_at(&self, x: u32, y: u32) -> Vec<f64> {
+    let i = (y * self.width + x) as usize;
+    let stride = aov::stride(&self.aovs);
+    let sums = &self.aov_sums[i * stride..(i + 1) * stride];
+
+    if self.weights[i].abs() < EPSILON {
+      return sums.to_vec();
+    }
+
+    sums.iter().map(|v| v / self.weights[i]).collect()
+  }
+
+  // One AOV as an image of its own, with single channel AOVs in grey. None
+  // if the frame wasn't rendered with it.
+  pub fn aov_canvas(&self, aov: Aov) -> Option<Canvas> {
+    let index = self.aovs.iter().position(|a| *a == aov)?;
+    let offset = aov::stride(&self.aovs[..index]);
+    let mut canvas = Canvas::new(self.width, self.height);
+
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let values = &self.aovs_at(x, y)[offset..];
+        let color = match aov.channels() {
+          1 => Color { r: values[0], g: values[0], b: values[0] },
+          _ => Color { r: values[0], g: values[1], b: values[2] },
+        };
+
+        canvas.set_pixel(x, y, color);
+      }
+    }
+
+    Some(canvas)
+  }
+
+  #[allow(dead_code, reason = "main.rs only reads whole canvases")]
+  pub fn samples_at(&self, x: u32, y: u32) -> u32 {
+    self.sample_counts[(y * self.width + x) as usize]
+  }
+
+  pub fn canvas(&self) -> Canvas {
+    let mut canvas = Canvas::new(self.width, self.height);
+
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let sample = self.sample_at(x, y);
+        canvas.set_pixel(x, y, sample.color);
+        canvas.set_alpha(x, y, sample.alpha);
+      }
+    }
+
+    canvas
+  }
+
+  // Just the pixels inside `region`, which must lie inside the frame.
+  pub fn crop(&self, region: Tile) -> Canvas {
+    let mut canvas = Canvas::new(region.width, region.height);
+
+    for y in 0..region.height {
+      for x in 0..region.width {
+        let sample = self.sample_at(region.x + x, region.y + y);
+        canvas.set_pixel(x, y, sample.color);
+        canvas.set_alpha(x, y, sample.alpha);
+      }
+    }
+
+    canvas
+  }
+
+  // Splits the frame into one view per tile, in the order given. The tiles
+  // must come from `tile::tiles` with the same size.
+  pub fn tiles_mut(&mut self, tiles: &[Tile], size: u32) -> Vec<TileView<'_>> {
+    if self.width == 0 {
+      return Vec::new();
+    }
+
+    let size = size.max(1) as usize;
+    let width = self.width as usize;
+    let columns = width.div_ceil(size);
+    let mut grid: Vec<Vec<TileRow>> = (0..columns * (self.height as usize).div_ceil(size)).map(|_| Vec::new()).collect();
+
+    let stride = aov::stride(&self.aovs);
+    let rows = self
+      .sums
+      .chunks_mut(width)
+      .zip(self.weights.chunks_mut(width))
+      .zip(self.sample_counts.chunks_mut(width))
+      .zip(chunks(&mut self.aov_sums, width * stride));
+    for (y, (((sum_row, weight_row), count_row), aov_row)) in rows.enumerate() {
+      let pieces = sum_row
+        .chunks_mut(size)
+        .zip(weight_row.chunks_mut(size))
+        .zip(count_row.chunks_mut(size))
+        .zip(chunks(aov_row, size * stride));
+
+      for (column, (((sums, weights), counts), aov_sums)) in pieces.enumerate() {
+        grid[(y / size) * columns + column].push(TileRow { sums, weights, counts, aov_sums });
+      }
+    }
+
+    let mut grid: Vec<Option<Vec<_>>> = grid.into_iter().map(Some).collect();
+
+    tiles
+      .iter()
+      .map(|t| TileView {
+        tile: *t,
+        rows: grid[(t.y as usize / size) * columns + t.x as usize / size].take().unwrap(),
+      })
+      .collect()
+  }
+
+  // Debug view of how many samples each pixel took, from blue for the
+  // fewest to red for the most.
+  #[allow(dead_code, reason = "a debug view for tuning adaptive sampling, which main.rs doesn't use")]
+  pub fn heatmap(&self) -> Canvas {
+    let max = self.sample_counts.iter().cloned().max().unwrap_or(0).max(1) as f64;
+    let mut canvas = Canvas::new(self.width, self.height);
+
+    for y in 0..self.height {
+      for x in 0..self.width {
+        canvas.set_pixel(x, y, Color::heat(self.samples_at(x, y) as f64 / max));
+      }
+    }
+
+    canvas
+  }
+}
+
+// Splits `values` into chunks of `size`, followed by as many empty chunks as
+// needed, so buffers with nothing in them still line up with the others.
+fn chunks<T>(values: &mut [T], size: usize) -> impl Iterator<Item = &mut [T]> {
+  values.chunks_mut(size.max(1)).chain(std::iter::repeat_with(Default::default))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::Color;
+  use crate::aov::Aov;
+  use crate::frame::{Frame, Sample};
+  use crate::tile::{tiles, TileOrder};
+
+  #[test]
+  fn heatmap_runs_from_blue_to_red() {
+    let mut f = Frame::new(2, 1);
+    f.sample_counts = vec![4, 16];
+
+    let heatmap = f.heatmap();
+
+    assert_eq!(heatmap.get_pixel(0, 0), Color { r: 0.25, g: 0.5, b: 0.75 });
+    assert_eq!(heatmap.get_pixel(1, 0), Color { r: 1.0, g: 0.0, b: 0.0 });
+  }
+
+  #[test]
+  fn pixels_are_the_weighted_average_of_their_samples() {
+    let mut f = Frame::new(1, 1);
+    f.sums[0] = Sample {
+      color: Color { r: 1.0, g: 0.5, b: 2.0 },
+      alpha: 1.5,
+    };
+    f.weights[0] = 2.0;
+
+    assert_eq!(f.pixel_at(0, 0), Color { r: 0.5, g: 0.25, b: 1.0 });
+    assert_eq!(f.alpha_at(0, 0), 0.75);
+    assert_eq!(f.canvas().get_alpha(0, 0), 0.75);
+  }
+
+  #[test]
+  fn tile_views_write_into_their_own_part_of_the_frame() {
+    let mut f = Frame::new(5, 3);
+    let t = tiles(5, 3, 2, TileOrder::Spiral);
+
+    for (i, view) in f.tiles_mut(&t, 2).into_iter().enumerate() {
+      assert_eq!(view.rows.len() as u32, view.tile.height);
+
+      for row in view.rows {
+        assert_eq!(row.sums.len() as u32, view.tile.width);
+        assert!(row.aov_sums.is_empty());
+        row.counts.fill(i as u32 + 1);
+      }
+    }
+
+    for (i, tile) in t.iter().enumerate() {
+      assert_eq!(f.samples_at(tile.x, tile.y), i as u32 + 1);
+      assert_eq!(f.samples_at(tile.x + tile.width - 1, tile.y + tile.height - 1), i as u32 + 1);
+    }
+  }
+
+  #[test]
+  fn aovs_are_split_with_their_pixels() {
+    let mut f = Frame::with_aovs(3, 1, &[Aov::Depth, Aov::Normal]);
+    let t = tiles(3, 1, 2, TileOrder::Scanline);
+
+    let lengths: Vec<usize> = f.tiles_mut(&t, 2).into_iter().map(|v| v.rows[0].aov_sums.len()).collect();
+    assert_eq!(lengths, vec![8, 4]);
+
+    f.aov_sums[8..12].copy_from_slice(&[3.0, 0.5, 1.0, 0.0]);
+    f.weights[2] = 0.5;
+    assert_eq!(f.aovs_at(2, 0), vec![6.0, 1.0, 2.0, 0.0]);
+    assert_eq!(f.aov_canvas(Aov::Normal).unwrap().get_pixel(2, 0), Color { r: 1.0, g: 2.0, b: 0.0 });
+    assert_eq!(f.aov_canvas(Aov::Albedo), None);
+  }
+}
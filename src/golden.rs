@@ -0,0 +1,250 @@
+use crate::canvas::{Canvas, SaveError};
+use crate::export::ExportOptions;
+use crate::formats::{self, ImageFormat};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// How far a render may drift from its golden image before it counts as a
+// regression.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Tolerance {
+  pub max_rmse: f64,
+  pub min_ssim: f64,
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+  Io(io::Error),
+  // There is no golden image yet; bless the render to create one
+  Missing(PathBuf),
+  // The render is out of tolerance. The render itself and a heatmap of the
+  // difference were written next to each other in the failures directory.
+  Mismatch { rmse: f64, ssim: f64, diff: PathBuf },
+  // The render isn't the size of its golden image. The render was written
+  // to the failures directory, at `actual`.
+  SizeMismatch { expected: (u32, u32), found: (u32, u32), actual: PathBuf },
+}
+
+impl fmt::Display for GoldenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GoldenError::Io(e) => write!(f, "{}", e),
+      GoldenError::Missing(path) => write!(f, "no golden image at {}, run with {}=1 to create it", path.display(), BLESS_VARIABLE),
+      GoldenError::Mismatch { rmse, ssim, diff } => write!(f, "render differs from its golden image (RMSE {:.5}, SSIM {:.5}), see {}", rmse, ssim, diff.display()),
+      GoldenError::SizeMismatch { expected, found, actual } => write!(f, "render is {}x{} but its golden image is {}x{}, see {}", found.0, found.1, expected.0, expected.1, actual.display()),
+    }
+  }
+}
+
+impl From<io::Error> for GoldenError {
+  fn from(e: io::Error) -> GoldenError {
+    GoldenError::Io(e)
+  }
+}
+
+impl From<SaveError> for GoldenError {
+  fn from(e: SaveError) -> GoldenError {
+    match e {
+      SaveError::Io(e) => GoldenError::Io(e),
+      e => GoldenError::Io(io::Error::other(e.to_string())),
+    }
+  }
+}
+
+// Set to overwrite golden images with the current renders instead of
+// comparing against them.
+pub const BLESS_VARIABLE: &str = "RAYTRACER_BLESS";
+
+// Compares renders against golden images stored as linear PFM files, named
+// after the test that renders them.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Golden {
+  pub dir: PathBuf,
+  // Where renders that fail and their diffs are written
+  pub failures: PathBuf,
+  pub tolerance: Tolerance,
+  pub bless: bool,
+}
+
+impl Golden {
+  pub fn new(dir: impl AsRef<Path>, failures: impl AsRef<Path>) -> Golden {
+    Golden {
+      dir: dir.as_ref().to_path_buf(),
+      failures: failures.as_ref().to_path_buf(),
+      tolerance: Tolerance { max_rmse: 0.002, min_ssim: 0.99 },
+      bless: std::env::var_os(BLESS_VARIABLE).is_some(),
+    }
+  }
+
+  pub fn check(&self, name: &str, actual: &Canvas) -> Result<(), GoldenError> {
+    let path = self.dir.join(format!("{}.pfm", name));
+
+    if self.bless {
+      fs::create_dir_all(&self.dir)?;
+      let mut sink = BufWriter::new(File::create(&path)?);
+      formats::write_pfm(actual, &mut sink, |c| c)?;
+      sink.flush()?;
+
+      return Ok(());
+    }
+
+    let expected = match File::open(&path) {
+      Ok(file) => formats::read_pfm(&mut BufReader::new(file))?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(GoldenError::Missing(path)),
+      Err(e) => return Err(GoldenError::Io(e)),
+    };
+
+    let same_size = expected.width() == actual.width() && expected.height() == actual.height();
+    let (rmse, ssim) = if same_size { (actual.rmse(&expected), actual.ssim(&expected)) } else { (f64::INFINITY, 0.0) };
+    if rmse <= self.tolerance.max_rmse && ssim >= self.tolerance.min_ssim {
+      return Ok(());
+    }
+
+    fs::create_dir_all(&self.failures)?;
+    let actual_path = self.failures.join(format!("{}-actual.pfm", name));
+    actual.save_as(&actual_path, ImageFormat::Pfm, &ExportOptions::new())?;
+
+    if !same_size {
+      return Err(GoldenError::SizeMismatch {
+        expected: (expected.width(), expected.height()),
+        found: (actual.width(), actual.height()),
+        actual: actual_path,
+      });
+    }
+
+    let diff = self.failures.join(format!("{}-diff.png", name));
+    actual.diff_heatmap(&expected, 0.1).save_with(&diff, &ExportOptions::raw())?;
+
+    Err(GoldenError::Mismatch { rmse, ssim, diff })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::camera::Camera;
+  use crate::canvas::{Canvas, Color};
+  use crate::cube::Cube;
+  use crate::golden::{Golden, GoldenError};
+  use crate::material::Material;
+  use crate::matrix::Matrix;
+  use crate::object::Object;
+  use crate::pattern::{Pattern, StripedPattern};
+  use crate::plane::Plane;
+  use crate::point::Point;
+  use crate::point_light::PointLight;
+  use crate::sampler::Sampler;
+  use crate::sphere::Sphere;
+  use crate::vector::Vector;
+  use crate::world::World;
+  use std::path::Path;
+
+  fn golden() -> Golden {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    Golden::new(root.join("golden"), root.join("target").join("golden-failures"))
+  }
+
+  fn scratch(name: &str) -> Golden {
+    let dir = std::env::temp_dir().join(format!("raytracer-golden-{}-{}", name, std::process::id()));
+    let mut g = Golden::new(&dir, dir.join("failures"));
+    g.bless = false;
+
+    g
+  }
+
+  fn camera(width: u32, height: u32, from: Point, to: Point) -> Camera {
+    let mut c = Camera::new(width, height, std::f64::consts::PI / 3.0);
+    c.sampler = Sampler::Grid(2);
+    c.transform = Camera::view_transform(from, to, Vector { x: 0.0, y: 1.0, z: 0.0 });
+
+    c
+  }
+
+  fn assert_matches_golden(name: &str, canvas: &Canvas) {
+    if let Err(e) = golden().check(name, canvas) {
+      panic!("{}: {}", name, e);
+    }
+  }
+
+  #[test]
+  fn the_harness_passes_matches_and_reports_differences() {
+    let g = scratch("harness");
+    let mut c = Canvas::new(8, 8);
+    c.set_pixel(3, 3, Color { r: 0.5, g: 0.5, b: 0.5 });
+
+    assert!(matches!(g.check("square", &c), Err(GoldenError::Missing(_))));
+
+    let blessing = Golden { bless: true, ..g.clone() };
+    blessing.check("square", &c).unwrap();
+    g.check("square", &c).unwrap();
+
+    c.set_pixel(4, 4, Color { r: 1.0, g: 1.0, b: 1.0 });
+    match g.check("square", &c) {
+      Err(GoldenError::Mismatch { rmse, diff, .. }) => {
+        assert!(rmse > 0.0);
+        assert!(diff.exists());
+        assert!(g.failures.join("square-actual.pfm").exists());
+      }
+      other => panic!("expected a mismatch, got {:?}", other),
+    }
+
+    match g.check("square", &Canvas::new(4, 8)) {
+      Err(GoldenError::SizeMismatch { expected, found, actual }) => {
+        assert_eq!((expected, found), ((8, 8), (4, 8)));
+        assert!(actual.exists());
+      }
+      other => panic!("expected a size mismatch, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(&g.dir).unwrap();
+  }
+
+  #[test]
+  fn default_world_matches_golden() {
+    let c = camera(48, 32, Point { x: 0.0, y: 1.5, z: -5.0 }, Point { x: 0.0, y: 0.0, z: 0.0 });
+
+    assert_matches_golden("default_world", &c.render(World::default(), 5));
+  }
+
+  #[test]
+  fn materials_scene_matches_golden() {
+    let mut floor = Plane::new();
+    floor.transform = Matrix::translate(0.0, -1.0, 0.0);
+    let mut stripes = StripedPattern::new(Color { r: 1.0, g: 0.25, b: 0.25 }, Color { r: 0.25, g: 0.25, b: 1.0 });
+    stripes.transform = Matrix::scale_linear(0.5);
+    floor.material.pattern = Some(Pattern::Stripe(stripes));
+    floor.material.reflective = 0.2;
+
+    let mut mirror = Sphere::new();
+    mirror.transform = Matrix::translate(-1.2, 0.0, 0.5);
+    mirror.material.color = Color { r: 0.1, g: 0.1, b: 0.1 };
+    mirror.material.reflective = 0.9;
+
+    let mut glass = Sphere::new();
+    glass.transform = Matrix::translate(1.0, -0.3, -0.5) * Matrix::scale_linear(0.7);
+    glass.material = Material {
+      diffuse: 0.1,
+      transparency: 0.9,
+      reflective: 0.9,
+      refractive_index: 1.5,
+      ..Material::new()
+    };
+
+    let mut cube = Cube::new();
+    cube.transform = Matrix::translate(0.3, -0.6, 2.0) * Matrix::rotate_y(0.6) * Matrix::scale_linear(0.4);
+    cube.material.color = Color { r: 0.2, g: 0.8, b: 0.3 };
+
+    let mut w = World::new();
+    w.objects = vec![Object::Plane(floor), Object::Sphere(mirror), Object::Sphere(glass), Object::Cube(cube)];
+    w.lights = vec![PointLight {
+      position: Point { x: -5.0, y: 8.0, z: -6.0 },
+      intensity: Color { r: 1.0, g: 1.0, b: 1.0 },
+    }];
+
+    let c = camera(48, 32, Point { x: 0.0, y: 1.5, z: -5.0 }, Point { x: 0.0, y: 0.0, z: 0.5 });
+
+    assert_matches_golden("materials", &c.render(w, 5));
+  }
+}
@@ -4,16 +4,19 @@ use crate::vector::Vector;
 use crate::point::Point;
 use crate::utils::EPSILON;
 use crate::object::{Object,Intersectable};
+use crate::material::Material;
 
 use std::cmp::Ordering::Equal;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Intersection<'a> {
   pub time: f64,
-  pub object: &'a Object
+  pub object: &'a Object,
+  // Position of `object` in the world's objects
+  pub index: usize,
 }
 
-impl Intersection<'_> {
+impl<'a> Intersection<'a> {
   pub fn hit(intersections: Vec<Intersection>) -> Option<Intersection> {
     let mut copy = intersections.clone();
     copy.retain(|a| a.time > 0.0);
@@ -39,7 +42,7 @@ impl Intersection<'_> {
     Some(copy[0])
   }
 
-  pub fn prepare_computations_with_intersections(&self, r: Ray, intersections: Vec<Intersection>) -> Computations {
+  pub fn prepare_computations_with_intersections(&self, r: Ray, intersections: Vec<Intersection>) -> Computations<'a> {
     let mut comps = self.prepare_computations(r);
     let hit = Intersection::hit(intersections.clone());
 
@@ -47,6 +50,8 @@ impl Intersection<'_> {
 
     let mut n1: f64 = 1.0;
     let mut n2: f64 = 1.0;
+    let mut n1_medium: Option<Material> = None;
+    let mut n2_medium: Option<Material> = None;
 
     for i in intersections {
       if i == hit.unwrap() {
@@ -54,6 +59,7 @@ impl Intersection<'_> {
           n1 = 1.0;
         } else {
           n1 = containers.last().unwrap().material().refractive_index;
+          n1_medium = Some(containers.last().unwrap().material());
         }
       }
 
@@ -69,20 +75,23 @@ impl Intersection<'_> {
           n2 = 1.0;
         } else {
           n2 = containers.last().unwrap().material().refractive_index;
+          n2_medium = Some(containers.last().unwrap().material());
         }
       }
     }
 
     comps.n1 = n1;
     comps.n2 = n2;
+    comps.n1_medium = n1_medium;
+    comps.n2_medium = n2_medium;
 
     comps
   }
 
-  pub fn prepare_computations(&self, ray: Ray) -> Computations {
+  pub fn prepare_computations(&self, ray: Ray) -> Computations<'a> {
     let point = ray.position(self.time);
 
-    let mut normal = self.object.normal(point);
+    let mut normal = self.object.at_time(ray.time).normal(point);
     let eye_vector = -ray.direction;
 
     let reflect_vector = ray.direction.reflect(normal);
@@ -101,6 +110,7 @@ impl Intersection<'_> {
     Computations {
       time: self.time,
       object: self.object,
+      index: self.index,
       point,
       eye_vector,
       normal,
@@ -110,6 +120,9 @@ impl Intersection<'_> {
       under_point,
       n1: 1.0,
       n2: 1.0,
+      n1_medium: None,
+      n2_medium: None,
+      ray_time: ray.time,
     }
   }
 }
@@ -118,6 +131,7 @@ impl Intersection<'_> {
 pub struct Computations<'a> {
   pub time: f64,
   pub object: &'a Object,
+  pub index: usize,
   pub point: Point,
   pub eye_vector: Vector,
   pub normal: Vector,
@@ -127,6 +141,11 @@ pub struct Computations<'a> {
   pub under_point: Point,
   pub n1: f64,
   pub n2: f64,
+  // Materials of the objects the ray is leaving and entering, used to
+  // attenuate reflected and refracted rays travelling through them.
+  pub n1_medium: Option<Material>,
+  pub n2_medium: Option<Material>,
+  pub ray_time: f64,
 }
 
 #[cfg(test)]
@@ -141,7 +160,7 @@ mod tests {
   #[test]
   fn an_intersection_encapsulates_time_and_object() {
     let s = Sphere::new();
-    let i = Intersection { time: 3.5, object: &Object::Sphere(s) };
+    let i = Intersection { time: 3.5, object: &Object::Sphere(s), index: 0 };
 
     assert_eq!(i.time, 3.5);
     assert_eq!(i.object, &Object::Sphere(s));
@@ -150,8 +169,8 @@ mod tests {
   #[test]
   fn the_hit_when_all_intersections_positive() {
     let s = Sphere::new();
-    let i1 = Intersection { time: 1.0, object: &Object::Sphere(s) };
-    let i2 = Intersection { time: 2.0, object: &Object::Sphere(s) };
+    let i1 = Intersection { time: 1.0, object: &Object::Sphere(s), index: 0 };
+    let i2 = Intersection { time: 2.0, object: &Object::Sphere(s), index: 0 };
     let intersections = vec![i1, i2];
 
     assert_eq!(Intersection::hit(intersections).unwrap(), i1);
@@ -160,8 +179,8 @@ mod tests {
   #[test]
   fn the_hit_when_some_intersections_negative() {
     let s = Sphere::new();
-    let i1 = Intersection { time: -1.0, object: &Object::Sphere(s) };
-    let i2 = Intersection { time: 1.0, object: &Object::Sphere(s) };
+    let i1 = Intersection { time: -1.0, object: &Object::Sphere(s), index: 0 };
+    let i2 = Intersection { time: 1.0, object: &Object::Sphere(s), index: 0 };
     let intersections = vec![i1, i2];
 
     assert_eq!(Intersection::hit(intersections).unwrap(), i2);
@@ -170,8 +189,8 @@ mod tests {
   #[test]
   fn the_hit_when_all_intersections_negative() {
     let s = Sphere::new();
-    let i1 = Intersection { time: -2.0, object: &Object::Sphere(s) };
-    let i2 = Intersection { time: -1.0, object: &Object::Sphere(s) };
+    let i1 = Intersection { time: -2.0, object: &Object::Sphere(s), index: 0 };
+    let i2 = Intersection { time: -1.0, object: &Object::Sphere(s), index: 0 };
     let intersections = vec![i1, i2];
 
     assert_eq!(Intersection::hit(intersections), None);
@@ -180,10 +199,10 @@ mod tests {
   #[test]
   fn the_lowest_non_negative_intersection() {
     let s = Sphere::new();
-    let i1 = Intersection { time: 5.0, object: &Object::Sphere(s) };
-    let i2 = Intersection { time: 7.0, object: &Object::Sphere(s) };
-    let i3 = Intersection { time: -3.0, object: &Object::Sphere(s) };
-    let i4 = Intersection { time: 2.0, object: &Object::Sphere(s) };
+    let i1 = Intersection { time: 5.0, object: &Object::Sphere(s), index: 0 };
+    let i2 = Intersection { time: 7.0, object: &Object::Sphere(s), index: 0 };
+    let i3 = Intersection { time: -3.0, object: &Object::Sphere(s), index: 0 };
+    let i4 = Intersection { time: 2.0, object: &Object::Sphere(s), index: 0 };
     let intersections = vec![i1, i2, i3, i4];
 
     assert_eq!(Intersection::hit(intersections).unwrap(), i4);
@@ -191,9 +210,9 @@ mod tests {
 
   #[test]
   fn precomputing_the_state_of_an_intersection() {
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let s = Sphere::new();
-    let i = Intersection { time: 4.0, object: &Object::Sphere(s) };
+    let i = Intersection { time: 4.0, object: &Object::Sphere(s), index: 0 };
 
     let comps = i.prepare_computations(r);
 
@@ -206,24 +225,24 @@ mod tests {
 
   #[test]
   fn the_hit_when_intersection_occurs_outside() {
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let s = Sphere::new();
-    let i = Intersection { time: 4.0, object: &Object::Sphere(s) };
+    let i = Intersection { time: 4.0, object: &Object::Sphere(s), index: 0 };
 
     let comps = i.prepare_computations(r);
 
-    assert_eq!(comps.inside, false);
+    assert!(!comps.inside);
   }
 
   #[test]
   fn the_hit_when_intersection_occurs_inside() {
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let s = Sphere::new();
-    let i = Intersection { time: 1.0, object: &Object::Sphere(s) };
+    let i = Intersection { time: 1.0, object: &Object::Sphere(s), index: 0 };
 
     let comps = i.prepare_computations(r);
 
-    assert_eq!(comps.inside, true);
+    assert!(comps.inside);
     assert_eq!(comps.point, Point { x: 0.0, y: 0.0, z: 1.0 });
     assert_eq!(comps.eye_vector, Vector { x: 0.0, y: 0.0, z: -1.0 });
     assert_eq!(comps.normal, Vector { x: 0.0, y: 0.0, z: -1.0 });
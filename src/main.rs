@@ -1,48 +1,80 @@
+mod aov;
 mod camera;
 mod canvas;
+mod checkpoint;
+mod cli;
 mod cube;
+mod denoise;
+#[cfg(test)]
+mod diff;
+mod export;
+mod filter;
+mod formats;
+mod frame;
+#[cfg(test)]
+mod golden;
 mod intersection;
 mod material;
 mod matrix;
+mod medium;
 mod object;
 mod pattern;
 mod plane;
 mod point;
 mod point_light;
+mod post;
+mod progress;
 mod ray;
+mod ray_tree;
+mod rng;
+mod sampler;
 mod sphere;
+mod tile;
+mod tone_map;
 mod utils;
 mod vector;
 mod world;
 
 use camera::Camera;
 use canvas::Color;
+use cli::SnapshotInterval;
 use cube::Cube;
+use denoise::{Denoiser, Guides};
+use export::{AlphaMode, ExportOptions};
+use formats::ImageFormat;
+use frame::Frame;
 use material::Material;
 use matrix::Matrix;
 use object::Object;
 use pattern::{GradientPattern, Pattern, StripedPattern};
+use sampler::Sampler;
 use plane::Plane;
 use point::Point;
 use point_light::PointLight;
+use progress::RenderOptions;
 use sphere::Sphere;
 use vector::Vector;
 use world::World;
 
-use std::time::SystemTime;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime};
 
 fn main() {
+  let args = match cli::parse(std::env::args().skip(1)) {
+    Ok(args) => args,
+    Err(message) => {
+      eprintln!("{}\n{}", message, cli::USAGE);
+      std::process::exit(1);
+    }
+  };
+
+  // Colours are picked by eye, so they are sRGB values like any colour
+  // picker gives. Light intensities stay linear.
   let mut sp = StripedPattern::new(
-    Color {
-      r: 1.0,
-      g: 0.25,
-      b: 0.25,
-    },
-    Color {
-      r: 0.25,
-      g: 0.25,
-      b: 1.0,
-    },
+    Color::from_srgb(1.0, 0.25, 0.25),
+    Color::from_srgb(0.25, 0.25, 1.0),
   );
   sp.transform = Matrix::scale_linear(0.125)
     * Matrix::rotate_z(-std::f64::consts::PI / 4.0)
@@ -50,16 +82,8 @@ fn main() {
   let pattern = Pattern::Stripe(sp);
 
   let mut sp2 = GradientPattern::new(
-    Color {
-      r: 1.0,
-      g: 0.0,
-      b: 0.0,
-    },
-    Color {
-      r: 0.0,
-      g: 1.0,
-      b: 0.0,
-    },
+    Color::from_srgb(1.0, 0.0, 0.0),
+    Color::from_srgb(0.0, 1.0, 0.0),
   );
   sp2.transform = Matrix::rotate_z(std::f64::consts::PI / 4.0)
     * Matrix::translate(1.0, 0.0, 0.0)
@@ -67,16 +91,8 @@ fn main() {
   let pattern2 = Pattern::Gradient(sp2);
 
   let mut sp3 = StripedPattern::new(
-    Color {
-      r: 1.0,
-      g: 1.0,
-      b: 0.0,
-    },
-    Color {
-      r: 0.0,
-      g: 1.0,
-      b: 0.0,
-    },
+    Color::from_srgb(1.0, 1.0, 0.0),
+    Color::from_srgb(0.0, 1.0, 0.0),
   );
   sp3.transform = Matrix::scale_linear(0.25) * Matrix::rotate_x(std::f64::consts::PI / 4.0);
   let pattern3 = Pattern::Stripe(sp3);
@@ -84,11 +100,7 @@ fn main() {
   let mut floor = Plane::new();
   floor.transform = Matrix::translate(0.0, -1.0, 0.0);
   let mut fm = Material::new();
-  fm.color = Color {
-    r: 1.0,
-    g: 0.9,
-    b: 0.9,
-  };
+  fm.color = Color::from_srgb(1.0, 0.9, 0.9);
   fm.specular = 0.0;
   fm.reflective = 0.0;
   fm.pattern = Some(pattern);
@@ -97,11 +109,7 @@ fn main() {
 
   let mut glass_floor = Plane::new();
   let mut gfm = Material::new();
-  gfm.color = Color {
-    r: 0.0,
-    g: 0.0,
-    b: 0.25,
-  };
+  gfm.color = Color::from_srgb(0.0, 0.0, 0.25);
   gfm.diffuse = 0.1;
   gfm.ambient = 0.0;
   gfm.specular = 1.0;
@@ -113,11 +121,7 @@ fn main() {
 
   let mut roof = Plane::new();
   let mut m1 = Material::new();
-  m1.color = Color {
-    r: 1.0,
-    g: 0.9,
-    b: 0.9,
-  };
+  m1.color = Color::from_srgb(1.0, 0.9, 0.9);
   m1.specular = 0.0;
   m1.reflective = 0.0;
   roof.transform = Matrix::translate(0.0, 15.0, 0.0);
@@ -153,11 +157,7 @@ fn main() {
   let mut middle = Sphere::new();
   middle.transform = Matrix::translate(-7.5, 2.0, 5.0);
   let mut m2 = Material::new();
-  m2.color = Color {
-    r: 0.373,
-    g: 0.404,
-    b: 0.55,
-  };
+  m2.color = Color::from_srgb8(95, 103, 140);
   m2.ambient = 0.0;
   m2.diffuse = 0.2;
   m2.specular = 1.0;
@@ -172,11 +172,7 @@ fn main() {
     * Matrix::rotate_y(std::f64::consts::PI / 5.0)
     * Matrix::scale_linear(0.666);
   let mut mtop = Material::new();
-  mtop.color = Color {
-    r: 0.0,
-    g: 0.0,
-    b: 0.0,
-  };
+  mtop.color = Color::from_srgb(0.0, 0.0, 0.0);
   mtop.ambient = 0.0;
   mtop.diffuse = 0.0;
   mtop.specular = 1.0;
@@ -189,11 +185,7 @@ fn main() {
   let mut top_inside = Sphere::new();
   top_inside.transform = Matrix::translate(-0.75, 1.0, 0.5) * Matrix::scale_linear(0.5);
   let mut mtopi = Material::new();
-  mtopi.color = Color {
-    r: 0.0,
-    g: 0.0,
-    b: 0.0,
-  };
+  mtopi.color = Color::from_srgb(0.0, 0.0, 0.0);
   mtopi.ambient = 0.0;
   mtopi.diffuse = 0.0;
   mtopi.specular = 1.0;
@@ -206,11 +198,7 @@ fn main() {
   let mut right = Sphere::new();
   right.transform = Matrix::translate(1.1, 0.5, -0.5) * Matrix::scale_linear(0.5);
   let mut m3 = Material::new();
-  m3.color = Color {
-    r: 0.5,
-    g: 1.0,
-    b: 0.1,
-  };
+  m3.color = Color::from_srgb(0.5, 1.0, 0.1);
   m3.diffuse = 0.01;
   m3.specular = 1.0;
   m3.shininess = 300.0;
@@ -223,11 +211,7 @@ fn main() {
   let mut left = Sphere::new();
   left.transform = Matrix::translate(-1.5, 0.33, -1.0) * Matrix::scale_linear(0.33);
   let mut m4 = Material::new();
-  m4.color = Color {
-    r: 1.0,
-    g: 0.8,
-    b: 0.1,
-  };
+  m4.color = Color::from_srgb(1.0, 0.8, 0.1);
   m4.diffuse = 0.7;
   m4.specular = 0.3;
   m4.pattern = Some(pattern3);
@@ -309,6 +293,7 @@ fn main() {
       Object::Cube(top), /*Object::Sphere(top_inside)*/
     ],
     lights: vec![default_light /*, light1, light2, light3, light4*/],
+    fog: None,
   };
 
   // Settings for renderer
@@ -320,10 +305,11 @@ fn main() {
   // 7680x4320
   let width: u32 = 3840;
   let height = (width as f64 / 1.77777777777777778) as u32;
-  let antialias = true;
+  let sampler = Sampler::Grid(2);
   let recursion_depth = 5;
 
   let mut camera = Camera::new(width, height, std::f64::consts::PI / 3.0);
+  camera.sampler = sampler;
   camera.transform = Camera::view_transform(
     Point {
       x: 2.0,
@@ -342,19 +328,113 @@ fn main() {
     },
   );
 
+  if let Some((x, y)) = args.debug_pixel {
+    if x >= width || y >= height {
+      eprintln!("pixel ({}, {}) is outside the {}x{} image", x, y, width, height);
+      std::process::exit(1);
+    }
+
+    print!("{}", camera.debug_pixel(&world, x, y, recursion_depth));
+    return;
+  }
+
+  if let Err(e) = std::fs::create_dir_all("images") {
+    eprintln!("could not create the images directory: {}", e);
+    std::process::exit(1);
+  }
+
+  let filetime = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .expect("error")
+    .as_secs();
+
   let starttime = SystemTime::now()
     .duration_since(SystemTime::UNIX_EPOCH)
     .expect("error");
-  let canvas = camera.render(world, antialias, recursion_depth);
+  let mut options = RenderOptions::new();
+  options.time_budget = args.time_budget;
+  options.aovs = args.aovs.clone();
+  if args.denoise {
+    for aov in Denoiser::aovs() {
+      if !options.aovs.contains(&aov) {
+        options.aovs.push(aov);
+      }
+    }
+  }
+  options.observer = Some(Box::new(|p| {
+    let eta = p.eta().map_or(String::from("-"), |eta| format!("{:.0}s", eta.as_secs_f64()));
+    print!("\rpass {} {:5.1}% {} rays, ETA {}   ", p.pass + 1, p.fraction() * 100.0, p.rays_traced, eta);
+    std::io::stdout().flush().unwrap();
+  }));
+
+  let format = args.format.unwrap_or(ImageFormat::Png);
+  let mut export = ExportOptions::new();
+  export.alpha = args.alpha.unwrap_or(AlphaMode::Opaque);
+
+  let image = |frame: &Frame| {
+    let canvas = if args.denoise {
+      Denoiser::new().apply(&frame.canvas(), &Guides::from_frame(frame))
+    } else {
+      frame.canvas()
+    };
+
+    args.post.apply(&canvas)
+  };
+
+  let frame = if args.progressive {
+    let mut last_snapshot = Instant::now();
+    let hash = checkpoint::scene_hash(&camera, &world, recursion_depth, &options.aovs);
+    let save_checkpoint = |frame: &Frame| {
+      if let Some(path) = &args.checkpoint {
+        if let Err(e) = checkpoint::save(frame, hash, path) {
+          eprintln!("\ncould not save checkpoint: {}", e);
+        }
+      }
+    };
+
+    let start = match &args.checkpoint {
+      Some(path) if path.exists() => match checkpoint::load(path, &camera, hash) {
+        Ok(frame) => {
+          println!("Resuming after {} passes", frame.passes);
+          frame
+        }
+        Err(e) => {
+          eprintln!("could not resume from {}: {}", path.display(), e);
+          std::process::exit(1);
+        }
+      },
+      _ => Frame::with_aovs(width, height, &options.aovs),
+    };
+
+    let frame = camera.resume_progressive(world, start, recursion_depth, args.passes, &options, |frame| {
+      save_checkpoint(frame);
+
+      let due = match args.snapshot_every {
+        Some(SnapshotInterval::Passes(n)) => frame.passes % n.max(1) == 0,
+        Some(SnapshotInterval::Seconds(s)) => last_snapshot.elapsed().as_secs_f64() >= s,
+        None => false,
+      };
+
+      if due {
+        last_snapshot = Instant::now();
+        let snapshot = Path::new("images").join(format!("image-{}-{}x{}-pass{}.{}", filetime, width, height, frame.passes, format.extension()));
+        if let Err(e) = image(frame).save_as(&snapshot, format, &export) {
+          eprintln!("\ncould not save snapshot {}: {}", snapshot.display(), e);
+        }
+      }
+    });
+    save_checkpoint(&frame);
+
+    frame
+  } else {
+    camera.render_with(world, recursion_depth, &options)
+  };
+  println!();
   let endtime = SystemTime::now()
     .duration_since(SystemTime::UNIX_EPOCH)
     .expect("error");
 
-  if antialias {
-    println!("Anti-alias on");
-  } else {
-    println!("Anti-alias off");
-  }
+  println!("{} passes of {} samples per pixel", frame.passes, sampler.samples_per_pixel());
   println!("{}x{} = {} pixels", width, height, width * height);
   println!(
     "Render took {:.3} seconds",
@@ -365,10 +445,23 @@ fn main() {
     (endtime - starttime).as_micros() as f64 / (width * height) as f64
   );
 
-  let filetime = SystemTime::now()
-    .duration_since(SystemTime::UNIX_EPOCH)
-    .expect("error")
-    .as_secs();
-  let filename: &str = &format!("image-{}-{}x{}.png", filetime, width, height);
-  canvas.save(filename);
+  let filename = Path::new("images").join(format!("image-{}-{}x{}.{}", filetime, width, height, format.extension()));
+  if let Err(e) = image(&frame).save_as(&filename, format, &export) {
+    eprintln!("could not save {}: {}", filename.display(), e);
+    std::process::exit(1);
+  }
+
+  if !frame.aovs.is_empty() {
+    let filename = Path::new("images").join(format!("image-{}-{}x{}-aovs.exr", filetime, width, height));
+    let written = File::create(&filename).and_then(|file| {
+      let mut sink = BufWriter::new(file);
+      aov::write_exr_layers(&frame, &mut sink, &export)?;
+      sink.flush()
+    });
+
+    if let Err(e) = written {
+      eprintln!("could not save {}: {}", filename.display(), e);
+      std::process::exit(1);
+    }
+  }
 }
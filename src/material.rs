@@ -4,6 +4,15 @@ use crate::point::Point;
 use crate::point_light::PointLight;
 use crate::pattern::{Pattern,PatternTrait};
 use crate::object::Object;
+use crate::medium::Medium;
+
+// Wavelengths in micrometres that each colour channel is traced at when
+// refracting through a dispersive material, and the reference wavelength
+// (sodium D line) that `refractive_index` is quoted at.
+pub const RED_WAVELENGTH: f64 = 0.65;
+pub const GREEN_WAVELENGTH: f64 = 0.55;
+pub const BLUE_WAVELENGTH: f64 = 0.45;
+pub const REFERENCE_WAVELENGTH: f64 = 0.5893;
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Material {
@@ -16,6 +25,15 @@ pub struct Material {
   pub pattern: Option<Pattern>,
   pub refractive_index: f64,
   pub transparency: f64,
+  pub roughness: f64,
+  pub glossy_samples: u32,
+  pub absorption_color: Color,
+  pub absorption_density: f64,
+  pub dispersion: f64,
+  // Medium filling the object's interior. Light reaching it passes through
+  // the object's own surface, whether or not the object casts shadows, and
+  // is only blocked by what lies outside.
+  pub volume: Option<Medium>,
 }
 
 impl Material {
@@ -30,6 +48,35 @@ impl Material {
       pattern: None,
       refractive_index: 1.0,
       transparency: 0.0,
+      roughness: 0.0,
+      glossy_samples: 16,
+      absorption_color: Color { r: 1.0, g: 1.0, b: 1.0 },
+      absorption_density: 0.0,
+      dispersion: 0.0,
+      volume: None,
+    }
+  }
+
+  // Cauchy's equation, n = A + B / wavelength^2, with A chosen so that the
+  // index at the reference wavelength is `refractive_index` and B being
+  // `dispersion` in square micrometres (about 0.0042 for crown glass).
+  pub fn refractive_index_at(&self, wavelength: f64) -> f64 {
+    self.refractive_index + self.dispersion * (1.0 / wavelength.powi(2) - 1.0 / REFERENCE_WAVELENGTH.powi(2))
+  }
+
+  // Beer-Lambert: how much light survives `distance` units inside this
+  // material. Channels missing from `absorption_color` are absorbed fastest.
+  pub fn transmittance(&self, distance: f64) -> Color {
+    if self.absorption_density == 0.0 {
+      return Color { r: 1.0, g: 1.0, b: 1.0 };
+    }
+
+    let absorb = |c: f64| (-(1.0 - c) * self.absorption_density * distance).exp();
+
+    Color {
+      r: absorb(self.absorption_color.r),
+      g: absorb(self.absorption_color.g),
+      b: absorb(self.absorption_color.b),
     }
   }
 
@@ -77,15 +124,16 @@ impl Material {
     ambient + diffuse + specular
   }
 
-  pub fn lighting_with_object(&self, object: &Object, light: PointLight, position: Point, eye_vector: Vector, normal: Vector, in_shadow: bool) -> Color {
-    let color: Color;
-    if self.pattern.is_some() {
-      color = self.pattern.unwrap().color_at_object(object, position);
-    } else {
-      color = self.color;
+  // The surface's own colour at a point on the object, before any lighting.
+  pub fn color_at_object(&self, object: &Object, position: Point) -> Color {
+    match self.pattern {
+      Some(pattern) => pattern.color_at_object(object, position),
+      None => self.color,
     }
+  }
 
-    let effective_color = color * light.intensity;
+  pub fn lighting_with_object(&self, object: &Object, light: PointLight, position: Point, eye_vector: Vector, normal: Vector, in_shadow: bool) -> Color {
+    let effective_color = self.color_at_object(object, position) * light.intensity;
 
     let lightv = (light.position - position).normalize();
 
@@ -124,8 +172,9 @@ impl Material {
 
 #[cfg(test)]
 mod tests {
-  use crate::material::Material;
+  use crate::material::{Material, RED_WAVELENGTH, GREEN_WAVELENGTH, BLUE_WAVELENGTH, REFERENCE_WAVELENGTH};
   use crate::canvas::Color;
+  use crate::utils::equal;
   use crate::vector::Vector;
   use crate::point::Point;
   use crate::point_light::PointLight;
@@ -143,6 +192,50 @@ mod tests {
     assert_eq!(m.diffuse, 0.9);
     assert_eq!(m.specular, 0.9);
     assert_eq!(m.shininess, 200.0);
+    assert_eq!(m.roughness, 0.0);
+    assert_eq!(m.glossy_samples, 16);
+    assert_eq!(m.absorption_density, 0.0);
+    assert_eq!(m.dispersion, 0.0);
+  }
+
+  #[test]
+  fn non_dispersive_material_has_same_index_for_all_wavelengths() {
+    let mut m = Material::new();
+    m.refractive_index = 1.5;
+
+    assert_eq!(m.refractive_index_at(RED_WAVELENGTH), 1.5);
+    assert_eq!(m.refractive_index_at(BLUE_WAVELENGTH), 1.5);
+  }
+
+  #[test]
+  fn dispersive_material_bends_blue_more_than_red() {
+    let mut m = Material::new();
+    m.refractive_index = 1.5;
+    m.dispersion = 0.0042;
+
+    assert!(equal(m.refractive_index_at(REFERENCE_WAVELENGTH), 1.5));
+    assert!(m.refractive_index_at(BLUE_WAVELENGTH) > m.refractive_index_at(GREEN_WAVELENGTH));
+    assert!(m.refractive_index_at(GREEN_WAVELENGTH) > m.refractive_index_at(RED_WAVELENGTH));
+  }
+
+  #[test]
+  fn clear_material_transmits_everything() {
+    let m = Material::new();
+
+    assert_eq!(m.transmittance(100.0), Color { r: 1.0, g: 1.0, b: 1.0 });
+  }
+
+  #[test]
+  fn absorption_grows_with_distance() {
+    let mut m = Material::new();
+    m.absorption_color = Color { r: 1.0, g: 0.5, b: 0.0 };
+    m.absorption_density = 1.0;
+
+    let thin = m.transmittance(1.0);
+    let thick = m.transmittance(2.0);
+
+    assert_eq!(thin, Color { r: 1.0, g: (-0.5_f64).exp(), b: (-1.0_f64).exp() });
+    assert_eq!(thick, Color { r: 1.0, g: (-1.0_f64).exp(), b: (-2.0_f64).exp() });
   }
 
   #[test]
@@ -162,7 +255,7 @@ mod tests {
   fn eye_between_light_and_surface_eye_offset_45_degrees() {
     let material = Material::new();
     let position = Point { x: 0.0, y: 0.0, z: 0.0 };
-    let eye_vector = Vector { x: 0.0, y: (2.0 as f64).sqrt() / 2.0, z: -((2.0 as f64).sqrt() / 2.0) };
+    let eye_vector = Vector { x: 0.0, y: 2.0_f64.sqrt() / 2.0, z: -(2.0_f64.sqrt() / 2.0) };
     let normal = Vector { x: 0.0, y: 0.0, z: -1.0 };
     let light = PointLight { intensity: Color { r: 1.0, g: 1.0, b: 1.0 }, position: Point { x: 0.0, y: 0.0, z: -10.0 } };
 
@@ -188,7 +281,7 @@ mod tests {
   fn lighting_in_path_with_reflecting_vector() {
     let material = Material::new();
     let position = Point { x: 0.0, y: 0.0, z: 0.0 };
-    let eye_vector = Vector { x: 0.0, y: -((2.0 as f64).sqrt() / 2.0), z: -((2.0 as f64).sqrt() / 2.0) };
+    let eye_vector = Vector { x: 0.0, y: -(2.0_f64.sqrt() / 2.0), z: -(2.0_f64.sqrt() / 2.0) };
     let normal = Vector { x: 0.0, y: 0.0, z: -1.0 };
     let light = PointLight { intensity: Color { r: 1.0, g: 1.0, b: 1.0 }, position: Point { x: 0.0, y: 10.0, z: -10.0 } };
 
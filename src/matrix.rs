@@ -130,6 +130,88 @@ impl Matrix {
       ]
     }
   }
+
+  // Blends between two transforms made of translation, rotation and scale
+  // (no shear). Rotation takes the shortest way round, so keyframes should be
+  // less than half a turn apart.
+  pub fn interpolate(&self, other: &Matrix, t: f64) -> Matrix {
+    let (t1, q1, s1) = decompose(self);
+    let (t2, mut q2, s2) = decompose(other);
+
+    let mut dot = q1[0] * q2[0] + q1[1] * q2[1] + q1[2] * q2[2] + q1[3] * q2[3];
+    if dot < 0.0 {
+      q2 = [-q2[0], -q2[1], -q2[2], -q2[3]];
+      dot = -dot;
+    }
+
+    let (a, b) = if dot > 0.9995 {
+      (1.0 - t, t)
+    } else {
+      let theta = dot.acos();
+      (((1.0 - t) * theta).sin() / theta.sin(), (t * theta).sin() / theta.sin())
+    };
+
+    let mut q = [0.0; 4];
+    for i in 0..4 {
+      q[i] = a * q1[i] + b * q2[i];
+    }
+    let length = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    for c in q.iter_mut() {
+      *c /= length;
+    }
+
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+    Matrix::translate(lerp(t1[0], t2[0]), lerp(t1[1], t2[1]), lerp(t1[2], t2[2]))
+      * rotation(q)
+      * Matrix::scale(lerp(s1[0], s2[0]), lerp(s1[1], s2[1]), lerp(s1[2], s2[2]))
+  }
+}
+
+// Splits an affine matrix into translation, rotation quaternion (w, x, y, z)
+// and per-axis scale.
+fn decompose(m: &Matrix) -> ([f64; 3], [f64; 4], [f64; 3]) {
+  let translation = [m[0][3], m[1][3], m[2][3]];
+
+  let mut scale = [0.0; 3];
+  for (col, s) in scale.iter_mut().enumerate() {
+    *s = (m[0][col].powi(2) + m[1][col].powi(2) + m[2][col].powi(2)).sqrt();
+  }
+  if determinant4(m.data) < 0.0 {
+    scale[0] = -scale[0];
+  }
+
+  let r = |row: usize, col: usize| m[row][col] / scale[col];
+  let trace = r(0, 0) + r(1, 1) + r(2, 2);
+
+  let q = if trace > 0.0 {
+    let s = (trace + 1.0).sqrt() * 2.0;
+    [s / 4.0, (r(2, 1) - r(1, 2)) / s, (r(0, 2) - r(2, 0)) / s, (r(1, 0) - r(0, 1)) / s]
+  } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+    let s = (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt() * 2.0;
+    [(r(2, 1) - r(1, 2)) / s, s / 4.0, (r(0, 1) + r(1, 0)) / s, (r(0, 2) + r(2, 0)) / s]
+  } else if r(1, 1) > r(2, 2) {
+    let s = (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt() * 2.0;
+    [(r(0, 2) - r(2, 0)) / s, (r(0, 1) + r(1, 0)) / s, s / 4.0, (r(1, 2) + r(2, 1)) / s]
+  } else {
+    let s = (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt() * 2.0;
+    [(r(1, 0) - r(0, 1)) / s, (r(0, 2) + r(2, 0)) / s, (r(1, 2) + r(2, 1)) / s, s / 4.0]
+  };
+
+  (translation, q, scale)
+}
+
+fn rotation(q: [f64; 4]) -> Matrix {
+  let [w, x, y, z] = q;
+
+  Matrix {
+    data: [
+      [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+      [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+      [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+      [0.0, 0.0, 0.0, 1.0]
+    ]
+  }
 }
 
 impl PartialEq for Matrix {
@@ -634,7 +716,7 @@ mod tests {
     ];
 
     assert_eq!(super::determinant4(a), -2120.0);
-    assert_eq!(super::invertible(a), true);
+    assert!(super::invertible(a));
   }
 
   #[test]
@@ -647,7 +729,7 @@ mod tests {
     ];
 
     assert_eq!(super::determinant4(a), 0.0);
-    assert_eq!(super::invertible(a), false);
+    assert!(!super::invertible(a));
   }
 
   #[test]
@@ -901,4 +983,29 @@ mod tests {
 
     assert_eq!(t * p, Point { x: 15.0, y: 0.0, z: 7.0 });
   }
+
+  #[test]
+  fn interpolating_the_ends_gives_back_the_keyframes() {
+    let a = Matrix::translate(1.0, 2.0, 3.0) * Matrix::rotate_y(0.5) * Matrix::scale(1.0, 2.0, 3.0);
+    let b = Matrix::translate(-1.0, 0.0, 4.0) * Matrix::rotate_x(1.2) * Matrix::scale_linear(0.5);
+
+    assert_eq!(a.interpolate(&b, 0.0), a);
+    assert_eq!(a.interpolate(&b, 1.0), b);
+  }
+
+  #[test]
+  fn interpolating_translations_is_linear() {
+    let a = Matrix::translate(0.0, 0.0, 0.0);
+    let b = Matrix::translate(2.0, 4.0, -6.0);
+
+    assert_eq!(a.interpolate(&b, 0.25), Matrix::translate(0.5, 1.0, -1.5));
+  }
+
+  #[test]
+  fn interpolating_rotations_keeps_them_rigid() {
+    let a = Matrix::identity();
+    let b = Matrix::rotate_z(std::f64::consts::PI / 2.0);
+
+    assert_eq!(a.interpolate(&b, 0.5), Matrix::rotate_z(std::f64::consts::PI / 4.0));
+  }
 }
@@ -0,0 +1,91 @@
+use crate::canvas::Color;
+
+// Homogeneous participating medium, used both for fog filling the world and
+// for volumes inside objects.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Medium {
+  pub color: Color,
+  pub absorption: f64,
+  pub scattering: f64,
+  // Henyey-Greenstein asymmetry, -1 scatters everything back towards the
+  // light, 0 is isotropic and 1 scatters everything forwards.
+  pub anisotropy: f64,
+  pub steps: u32,
+}
+
+impl Medium {
+  #[allow(dead_code, reason = "the demo scene has no fog")]
+  pub fn new(absorption: f64, scattering: f64) -> Medium {
+    Medium {
+      color: Color { r: 1.0, g: 1.0, b: 1.0 },
+      absorption,
+      scattering,
+      anisotropy: 0.0,
+      steps: 16,
+    }
+  }
+
+  pub fn extinction(&self) -> f64 {
+    self.absorption + self.scattering
+  }
+
+  pub fn transmittance(&self, distance: f64) -> f64 {
+    (-self.extinction() * distance).exp()
+  }
+
+  pub fn phase(&self, cos_theta: f64) -> f64 {
+    let g = self.anisotropy;
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+
+    (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator.powf(1.5))
+  }
+
+  // How far a ray that escapes the scene is marched before the remaining
+  // light would be too faint to matter.
+  pub fn max_distance(&self) -> f64 {
+    if self.extinction() == 0.0 {
+      return 0.0;
+    }
+
+    -(0.001_f64).ln() / self.extinction()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::medium::Medium;
+  use crate::utils::equal;
+
+  #[test]
+  fn transmittance_falls_off_exponentially() {
+    let m = Medium::new(0.25, 0.25);
+
+    assert!(equal(m.transmittance(0.0), 1.0));
+    assert!(equal(m.transmittance(2.0), (-1.0_f64).exp()));
+  }
+
+  #[test]
+  fn isotropic_phase_is_uniform() {
+    let m = Medium::new(0.0, 1.0);
+    let expected = 1.0 / (4.0 * std::f64::consts::PI);
+
+    assert!(equal(m.phase(1.0), expected));
+    assert!(equal(m.phase(-1.0), expected));
+  }
+
+  #[test]
+  fn forward_scattering_favours_small_angles() {
+    let mut m = Medium::new(0.0, 1.0);
+    m.anisotropy = 0.7;
+
+    assert!(m.phase(1.0) > m.phase(0.0));
+    assert!(m.phase(0.0) > m.phase(-1.0));
+  }
+
+  #[test]
+  fn clear_medium_is_not_marched() {
+    let m = Medium::new(0.0, 0.0);
+
+    assert_eq!(m.max_distance(), 0.0);
+  }
+}
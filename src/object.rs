@@ -12,7 +12,9 @@ pub trait Intersectable {
   fn normal(&self, p: Point) -> Vector;
   fn material(&self) -> Material;
   fn transform(&self) -> Matrix;
+  fn transform_at(&self, time: f64) -> Matrix;
   fn casts_shadow(&self) -> bool;
+  fn holdout(&self) -> bool;
 }
 
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -47,6 +49,14 @@ impl Intersectable for Object {
     }
   }
 
+  fn transform_at(&self, time: f64) -> Matrix {
+    match *self {
+      Object::Sphere(ref s) => s.transform_at(time),
+      Object::Plane(ref p) => p.transform_at(time),
+      Object::Cube(ref c) => c.transform_at(time),
+    }
+  }
+
   fn material(&self) -> Material {
     match *self {
       Object::Sphere(ref s) => s.material(),
@@ -62,4 +72,24 @@ impl Intersectable for Object {
       Object::Cube(ref c) => c.casts_shadow(),
     }
   }
+
+  fn holdout(&self) -> bool {
+    match *self {
+      Object::Sphere(ref s) => s.holdout(),
+      Object::Plane(ref p) => p.holdout(),
+      Object::Cube(ref c) => c.holdout(),
+    }
+  }
+}
+
+impl Object {
+  // A still copy of the object as it is at `time`, for the code that works
+  // with a hit point rather than a ray.
+  pub fn at_time(&self, time: f64) -> Object {
+    match *self {
+      Object::Sphere(s) => Object::Sphere(Sphere { transform: s.transform_at(time), end_transform: None, ..s }),
+      Object::Plane(p) => Object::Plane(Plane { transform: p.transform_at(time), end_transform: None, ..p }),
+      Object::Cube(c) => Object::Cube(Cube { transform: c.transform_at(time), end_transform: None, ..c }),
+    }
+  }
 }
@@ -125,7 +125,7 @@ mod tests {
   fn creating_a_striped_pattern() {
     let black = Color { r: 0.0, g: 0.0, b: 0.0 };
     let white = Color { r: 1.0, g: 1.0, b: 1.0 };
-    let mut pa = StripedPattern::new(white, black);
+    let pa = StripedPattern::new(white, black);
 
     assert_eq!(pa.a, white);
     assert_eq!(pa.b, black);
@@ -136,7 +136,7 @@ mod tests {
     let black = Color { r: 0.0, g: 0.0, b: 0.0 };
     let white = Color { r: 1.0, g: 1.0, b: 1.0 };
 
-    let mut pa = StripedPattern::new(white, black);
+    let pa = StripedPattern::new(white, black);
 
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 0.0 }), white);
     assert_eq!(pa.color_at(Point { x: 0.0, y: 1.0, z: 0.0 }), white);
@@ -148,7 +148,7 @@ mod tests {
     let black = Color { r: 0.0, g: 0.0, b: 0.0 };
     let white = Color { r: 1.0, g: 1.0, b: 1.0 };
 
-    let mut pa = StripedPattern::new(white, black);
+    let pa = StripedPattern::new(white, black);
 
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 0.0 }), white);
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 1.0 }), white);
@@ -159,7 +159,7 @@ mod tests {
     let black = Color { r: 0.0, g: 0.0, b: 0.0 };
     let white = Color { r: 1.0, g: 1.0, b: 1.0 };
 
-    let mut pa = StripedPattern::new(white, black);
+    let pa = StripedPattern::new(white, black);
 
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 0.0 }), white);
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 0.0 }), white);
@@ -215,7 +215,7 @@ mod tests {
     let black = Color { r: 0.0, g: 0.0, b: 0.0 };
     let white = Color { r: 1.0, g: 1.0, b: 1.0 };
 
-    let mut pa = GradientPattern::new(white, black);
+    let pa = GradientPattern::new(white, black);
 
     assert_eq!(pa.color_at(Point { x: 0.0, y: 0.0, z: 0.0 }), white);
     assert_eq!(pa.color_at(Point { x: 0.25, y: 0.0, z: 0.0 }), Color { r: 0.75, g: 0.75, b: 0.75 });
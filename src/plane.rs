@@ -10,8 +10,13 @@ use crate::utils::EPSILON;
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Plane {
   pub transform: Matrix,
+  // Where the shape has moved to when the shutter closes, if it moves.
+  pub end_transform: Option<Matrix>,
   pub material: Material,
   pub casts_shadow: bool,
+  // Hidden from the camera and cut out of the alpha channel, but still
+  // blocks and shadows everything else. Mirrors and glass see it as black.
+  pub holdout: bool,
 }
 
 impl Intersectable for Plane {
@@ -20,7 +25,7 @@ impl Intersectable for Plane {
   }
 
   fn intersect(&self, r: Ray) -> Vec<f64> {
-    let ray2 = r * self.transform.inverse().unwrap();
+    let ray2 = r * self.transform_at(r.time).inverse().unwrap();
 
     if (ray2.direction.y).abs() < EPSILON {
       return vec![];
@@ -39,17 +44,30 @@ impl Intersectable for Plane {
     self.transform
   }
 
+  fn transform_at(&self, time: f64) -> Matrix {
+    match self.end_transform {
+      Some(end) => self.transform.interpolate(&end, time),
+      None => self.transform,
+    }
+  }
+
   fn casts_shadow(&self) -> bool {
     self.casts_shadow
   }
+
+  fn holdout(&self) -> bool {
+    self.holdout
+  }
 }
 
 impl Plane {
   pub fn new() -> Plane {
     Plane {
       transform: Matrix::identity(),
+      end_transform: None,
       material: Material::new(),
-      casts_shadow: true
+      casts_shadow: true,
+      holdout: false,
     }
   }
 
@@ -64,10 +82,7 @@ mod tests {
   use crate::plane::Plane;
   use crate::vector::Vector;
   use crate::point::Point;
-  use crate::matrix::Matrix;
-  use crate::material::Material;
-  use crate::canvas::Color;
-  use crate::object::{Object, Intersectable};
+  use crate::object::Intersectable;
 
   #[test]
   fn the_normal_of_a_plane_is_constant_everywhere() {
@@ -85,7 +100,7 @@ mod tests {
   #[test]
   fn intersect_with_a_ray_parallell_to_the_plane() {
     let p = Plane::new();
-    let r = Ray { origin: Point { x: 0.0, y: 10.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 10.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let intersections = p.intersect(r);
 
@@ -95,7 +110,7 @@ mod tests {
   #[test]
   fn intersect_with_a_ray_coplanar_to_the_plane() {
     let p = Plane::new();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let intersections = p.intersect(r);
 
@@ -105,7 +120,7 @@ mod tests {
   #[test]
   fn intersect_with_a_ray_from_above() {
     let p = Plane::new();
-    let r = Ray { origin: Point { x: 0.0, y: 1.0, z: 0.0 }, direction: Vector { x: 0.0, y: -1.0, z: 0.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 1.0, z: 0.0 }, direction: Vector { x: 0.0, y: -1.0, z: 0.0 }, time: 0.0 };
 
     let intersections = p.intersect(r);
 
@@ -116,7 +131,7 @@ mod tests {
   #[test]
   fn intersect_with_a_ray_from_below() {
     let p = Plane::new();
-    let r = Ray { origin: Point { x: 0.0, y: -1.0, z: 0.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: -1.0, z: 0.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
 
     let intersections = p.intersect(r);
 
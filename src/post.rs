@@ -0,0 +1,385 @@
+use crate::canvas::{Canvas, Color};
+use crate::rng::Rng;
+use crate::utils::number;
+use rayon::prelude::*;
+
+// Finishing effects for a rendered image. They all work on the linear HDR
+// canvas, so they run before exposure and tone mapping are applied on
+// export, and bright highlights still bloom and fringe at full strength.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Effect {
+  // Light above `threshold` spread out with a Gaussian `radius` pixels wide
+  // and added back on top, scaled by `strength`.
+  Bloom { threshold: f64, radius: u32, strength: f64 },
+  // Darkens towards the corners, which lose `strength` of their light.
+  Vignette { strength: f64 },
+  // Scales the red channel up and the blue channel down by `strength`
+  // around the centre of the image, like a lens with lateral aberration.
+  // The blue channel is scaled by 1 / (1 - strength), so `strength` is
+  // kept in [0, 1).
+  ChromaticAberration { strength: f64 },
+  // Monochrome noise of up to `amount` times each pixel's brightness,
+  // the same for the same seed.
+  Grain { amount: f64, seed: u64 },
+  // Per channel (value * gain + lift) ^ (1 / gamma), followed by a
+  // saturation change around each pixel's luminance.
+  Grade { lift: Color, gamma: Color, gain: Color, saturation: f64 },
+}
+
+// A chain of effects, applied in order.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PostProcess {
+  pub effects: Vec<Effect>,
+}
+
+impl PostProcess {
+  pub fn new() -> PostProcess {
+    PostProcess::default()
+  }
+
+  #[allow(dead_code, reason = "for chains built in code, main.rs parses them from --post")]
+  pub fn with(mut self, effect: Effect) -> PostProcess {
+    self.effects.push(effect);
+    self
+  }
+
+  // Alpha is left as it is.
+  pub fn apply(&self, canvas: &Canvas) -> Canvas {
+    self.effects.iter().fold(canvas.clone(), |c, effect| effect.apply(&c))
+  }
+
+  // Reads a chain from text, one effect after the other separated by `;`,
+  // each a name followed by `key=value` settings. Settings left out keep
+  // their defaults, colours are either `r,g,b` or a single grey value:
+  //
+  //   bloom threshold=1 radius=16 strength=0.3; vignette strength=0.4;
+  //   aberration strength=0.004; grain amount=0.05 seed=7;
+  //   grade lift=0.01 gamma=1.1 gain=1.2,1.1,1 saturation=0.9
+  pub fn parse(spec: &str) -> Result<PostProcess, String> {
+    let mut post = PostProcess::new();
+
+    for part in spec.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+      let mut words = part.split_whitespace();
+      let name = words.next().unwrap();
+      let mut effect = match name {
+        "bloom" => Effect::Bloom { threshold: 1.0, radius: 16, strength: 0.3 },
+        "vignette" => Effect::Vignette { strength: 0.4 },
+        "aberration" => Effect::ChromaticAberration { strength: 0.004 },
+        "grain" => Effect::Grain { amount: 0.05, seed: 0 },
+        "grade" => Effect::Grade {
+          lift: Color { r: 0.0, g: 0.0, b: 0.0 },
+          gamma: Color { r: 1.0, g: 1.0, b: 1.0 },
+          gain: Color { r: 1.0, g: 1.0, b: 1.0 },
+          saturation: 1.0,
+        },
+        _ => return Err(format!("unknown effect {}", name)),
+      };
+
+      for setting in words {
+        let (key, value) = setting.split_once('=').ok_or(format!("{} is not a key=value setting", setting))?;
+
+        match (&mut effect, key) {
+          (Effect::Bloom { threshold, .. }, "threshold") => *threshold = number(value)?,
+          (Effect::Bloom { radius, .. }, "radius") => *radius = number(value)?,
+          (Effect::Bloom { strength, .. }, "strength")
+          | (Effect::Vignette { strength }, "strength")
+          | (Effect::ChromaticAberration { strength }, "strength") => *strength = number(value)?,
+          (Effect::Grain { amount, .. }, "amount") => *amount = number(value)?,
+          (Effect::Grain { seed, .. }, "seed") => *seed = number(value)?,
+          (Effect::Grade { lift, .. }, "lift") => *lift = color(value)?,
+          (Effect::Grade { gamma, .. }, "gamma") => *gamma = color(value)?,
+          (Effect::Grade { gain, .. }, "gain") => *gain = color(value)?,
+          (Effect::Grade { saturation, .. }, "saturation") => *saturation = number(value)?,
+          _ => return Err(format!("{} has no setting {}", name, key)),
+        }
+      }
+
+      if let Effect::ChromaticAberration { strength } = effect {
+        if !(0.0..1.0).contains(&strength) {
+          return Err(format!("aberration strength {} is not between 0 and 1", strength));
+        }
+      }
+
+      post.effects.push(effect);
+    }
+
+    Ok(post)
+  }
+}
+
+impl Effect {
+  pub fn apply(&self, canvas: &Canvas) -> Canvas {
+    let (width, height) = (canvas.width() as f64, canvas.height() as f64);
+
+    match *self {
+      Effect::Bloom { threshold, radius, strength } => {
+        let bright = map_pixels(canvas, |x, y| {
+          let c = canvas.get_pixel(x, y);
+          let excess = |v: f64| (v - threshold).max(0.0);
+
+          Color { r: excess(c.r), g: excess(c.g), b: excess(c.b) }
+        });
+        let glow = blur(&bright, radius);
+
+        map_pixels(canvas, |x, y| canvas.get_pixel(x, y) + glow.get_pixel(x, y) * strength)
+      }
+      Effect::Vignette { strength } => map_pixels(canvas, |x, y| {
+        let dx = (x as f64 + 0.5) / width * 2.0 - 1.0;
+        let dy = (y as f64 + 0.5) / height * 2.0 - 1.0;
+
+        canvas.get_pixel(x, y) * (1.0 - strength * (dx * dx + dy * dy) / 2.0).max(0.0)
+      }),
+      Effect::ChromaticAberration { strength } => map_pixels(canvas, |x, y| {
+        let (cx, cy) = (width / 2.0, height / 2.0);
+        let (px, py) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+        let at = |scale: f64| sample(canvas, cx + px * scale, cy + py * scale);
+
+        Color {
+          r: at(1.0 / (1.0 + strength)).r,
+          g: canvas.get_pixel(x, y).g,
+          b: at(1.0 / (1.0 - strength)).b,
+        }
+      }),
+      Effect::Grain { amount, seed } => map_straight(canvas, |x, y, c| {
+        let mut rng = Rng::new(seed ^ (y as u64 * width as u64 + x as u64));
+
+        c * (1.0 + (rng.next_f64() * 2.0 - 1.0) * amount)
+      }),
+      Effect::Grade { lift, gamma, gain, saturation } => map_straight(canvas, |_, _, c| {
+        let grade = |v: f64, lift: f64, gamma: f64, gain: f64| (v * gain + lift).max(0.0).powf(1.0 / gamma);
+        let graded = Color {
+          r: grade(c.r, lift.r, gamma.r, gain.r),
+          g: grade(c.g, lift.g, gamma.g, gain.g),
+          b: grade(c.b, lift.b, gamma.b, gain.b),
+        };
+        let luminance = graded.luminance();
+        let grey = Color { r: luminance, g: luminance, b: luminance };
+
+        grey + (graded - grey) * saturation
+      }),
+    }
+  }
+}
+
+// A new canvas with `f` run for every pixel, rows in parallel. Alpha is
+// copied from `canvas`.
+fn map_pixels(canvas: &Canvas, f: impl Fn(u32, u32) -> Color + Sync) -> Canvas {
+  let width = canvas.width();
+  let pixels: Vec<Color> = (0..canvas.height()).into_par_iter().flat_map_iter(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| f(x, y)).collect();
+
+  let mut out = canvas.clone();
+  for (i, c) in pixels.into_iter().enumerate() {
+    out.set_pixel(i as u32 % width, i as u32 / width, c);
+  }
+
+  out
+}
+
+// Like `map_pixels`, but `f` sees each pixel's colour with its alpha
+// divided out, and the result is multiplied by it again, so effects that
+// aren't a plain scale of the colour leave transparent pixels transparent.
+fn map_straight(canvas: &Canvas, f: impl Fn(u32, u32, Color) -> Color + Sync) -> Canvas {
+  map_pixels(canvas, |x, y| {
+    let alpha = canvas.get_alpha(x, y);
+    if alpha == 0.0 {
+      return canvas.get_pixel(x, y);
+    }
+
+    f(x, y, canvas.get_pixel(x, y) * (1.0 / alpha)) * alpha
+  })
+}
+
+// Separable Gaussian blur with a standard deviation of a third of `radius`.
+fn blur(canvas: &Canvas, radius: u32) -> Canvas {
+  if radius == 0 {
+    return canvas.clone();
+  }
+
+  let sigma = radius as f64 / 3.0;
+  let weights: Vec<f64> = (0..=2 * radius).map(|i| (-((i as f64 - radius as f64).powi(2)) / (2.0 * sigma * sigma)).exp()).collect();
+  let total: f64 = weights.iter().sum();
+  let (width, height) = (canvas.width() as i64, canvas.height() as i64);
+
+  let pass = |source: &Canvas, dx: i64, dy: i64| {
+    map_pixels(source, |x, y| {
+      let mut sum = Color { r: 0.0, g: 0.0, b: 0.0 };
+
+      for (i, w) in weights.iter().copied().enumerate() {
+        let offset = i as i64 - radius as i64;
+        let qx = (x as i64 + offset * dx).clamp(0, width - 1);
+        let qy = (y as i64 + offset * dy).clamp(0, height - 1);
+
+        sum = sum + source.get_pixel(qx as u32, qy as u32) * w;
+      }
+
+      sum * (1.0 / total)
+    })
+  };
+
+  pass(&pass(canvas, 1, 0), 0, 1)
+}
+
+// Bilinear lookup at a position in pixels, clamped to the edges.
+fn sample(canvas: &Canvas, x: f64, y: f64) -> Color {
+  let x = (x - 0.5).clamp(0.0, (canvas.width() - 1) as f64);
+  let y = (y - 0.5).clamp(0.0, (canvas.height() - 1) as f64);
+  let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+  let (x1, y1) = ((x0 + 1).min(canvas.width() - 1), (y0 + 1).min(canvas.height() - 1));
+  let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+
+  let top = canvas.get_pixel(x0, y0) * (1.0 - tx) + canvas.get_pixel(x1, y0) * tx;
+  let bottom = canvas.get_pixel(x0, y1) * (1.0 - tx) + canvas.get_pixel(x1, y1) * tx;
+
+  top * (1.0 - ty) + bottom * ty
+}
+
+fn color(s: &str) -> Result<Color, String> {
+  let values = s.split(',').map(number).collect::<Result<Vec<f64>, _>>()?;
+
+  match values[..] {
+    [v] => Ok(Color { r: v, g: v, b: v }),
+    [r, g, b] => Ok(Color { r, g, b }),
+    _ => Err(format!("{} is not a colour", s)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::{Canvas, Color};
+  use crate::post::{Effect, PostProcess};
+
+  fn grey(v: f64) -> Color {
+    Color { r: v, g: v, b: v }
+  }
+
+  fn filled(width: u32, height: u32, c: Color) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    for y in 0..height {
+      for x in 0..width {
+        canvas.set_pixel(x, y, c);
+      }
+    }
+
+    canvas
+  }
+
+  #[test]
+  fn bloom_spreads_only_light_above_the_threshold() {
+    let mut c = filled(9, 9, grey(0.5));
+    c.set_pixel(4, 4, grey(10.0));
+
+    let bloomed = Effect::Bloom { threshold: 1.0, radius: 3, strength: 1.0 }.apply(&c);
+
+    assert!(bloomed.get_pixel(5, 4).r > 0.5);
+    assert_eq!(bloomed.get_pixel(0, 0), grey(0.5));
+    assert_eq!(Effect::Bloom { threshold: 1.0, radius: 3, strength: 1.0 }.apply(&filled(9, 9, grey(0.5))), filled(9, 9, grey(0.5)));
+  }
+
+  #[test]
+  fn vignette_darkens_the_corners_but_not_the_centre() {
+    let c = filled(11, 11, grey(1.0));
+
+    let v = Effect::Vignette { strength: 0.5 }.apply(&c);
+
+    assert_eq!(v.get_pixel(5, 5), grey(1.0));
+    assert!(v.get_pixel(0, 0).r < 0.6);
+  }
+
+  #[test]
+  fn chromatic_aberration_leaves_the_centre_and_green_alone() {
+    let mut c = Canvas::new(9, 9);
+    c.set_pixel(4, 4, grey(1.0));
+    c.set_pixel(8, 4, grey(1.0));
+
+    let a = Effect::ChromaticAberration { strength: 0.2 }.apply(&c);
+
+    assert_eq!(a.get_pixel(4, 4), grey(1.0));
+    assert_eq!(a.get_pixel(8, 4).g, 1.0);
+    assert!(a.get_pixel(8, 4).r < 1.0);
+  }
+
+  #[test]
+  fn grain_is_repeatable_and_keeps_black_black() {
+    let c = filled(4, 4, grey(0.5));
+    let grain = Effect::Grain { amount: 0.1, seed: 3 };
+
+    assert_eq!(grain.apply(&c), grain.apply(&c));
+    assert_ne!(grain.apply(&c), c);
+    assert_eq!(grain.apply(&Canvas::new(4, 4)), Canvas::new(4, 4));
+  }
+
+  #[test]
+  fn grading_applies_gain_lift_gamma_then_saturation() {
+    let c = filled(1, 1, Color { r: 0.5, g: 0.25, b: 0.0 });
+    let grade = Effect::Grade {
+      lift: grey(0.1),
+      gamma: grey(1.0),
+      gain: grey(2.0),
+      saturation: 1.0,
+    };
+
+    assert_eq!(grade.apply(&c).get_pixel(0, 0), Color { r: 1.1, g: 0.6, b: 0.1 });
+
+    let desaturate = Effect::Grade {
+      lift: grey(0.0),
+      gamma: grey(1.0),
+      gain: grey(1.0),
+      saturation: 0.0,
+    };
+    let luminance = c.get_pixel(0, 0).luminance();
+    assert_eq!(desaturate.apply(&c).get_pixel(0, 0), grey(luminance));
+  }
+
+  #[test]
+  fn chains_are_parsed_from_text() {
+    let post = PostProcess::parse("bloom threshold=2 radius=4; vignette ; grade gain=1.2,1.1,1 saturation=0.8").unwrap();
+
+    assert_eq!(
+      post,
+      PostProcess::new()
+        .with(Effect::Bloom { threshold: 2.0, radius: 4, strength: 0.3 })
+        .with(Effect::Vignette { strength: 0.4 })
+        .with(Effect::Grade {
+          lift: grey(0.0),
+          gamma: grey(1.0),
+          gain: Color { r: 1.2, g: 1.1, b: 1.0 },
+          saturation: 0.8,
+        })
+    );
+    assert_eq!(PostProcess::parse("blur"), Err(String::from("unknown effect blur")));
+    assert_eq!(PostProcess::parse("vignette radius=2"), Err(String::from("vignette has no setting radius")));
+    assert_eq!(PostProcess::parse("grain amount"), Err(String::from("amount is not a key=value setting")));
+  }
+
+  #[test]
+  fn aberration_strength_must_be_below_one() {
+    assert_eq!(PostProcess::parse("aberration strength=1"), Err(String::from("aberration strength 1 is not between 0 and 1")));
+    assert_eq!(PostProcess::parse("aberration strength=-0.1"), Err(String::from("aberration strength -0.1 is not between 0 and 1")));
+    assert!(PostProcess::parse("aberration strength=0").is_ok());
+  }
+
+  #[test]
+  fn effects_keep_alpha() {
+    let mut c = filled(3, 3, grey(0.5));
+    c.set_alpha(1, 1, 0.25);
+
+    let post = PostProcess::parse("bloom; vignette; aberration; grain; grade").unwrap().apply(&c);
+
+    assert_eq!(post.get_alpha(1, 1), 0.25);
+
+    // Grading sees colour with its alpha divided out, so lift can't put
+    // colour into transparent pixels
+    let mut c = filled(2, 1, grey(0.25));
+    c.set_alpha(0, 0, 0.5);
+    c.set_pixel(1, 0, grey(0.0));
+    c.set_alpha(1, 0, 0.0);
+
+    let graded = PostProcess::parse("grade lift=0.5 gamma=2").unwrap().apply(&c);
+    let grained = PostProcess::parse("grain amount=0.5").unwrap().apply(&graded);
+
+    assert_eq!(graded.get_pixel(0, 0), grey(0.5));
+    assert_eq!(graded.get_pixel(1, 0), grey(0.0));
+    assert_eq!(grained.get_pixel(1, 0), grey(0.0));
+    assert_eq!(grained.get_alpha(1, 0), 0.0);
+  }
+}
@@ -0,0 +1,133 @@
+use crate::aov::Aov;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Snapshot of how far a render pass has come, handed to the observer every
+// time a tile finishes.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Progress {
+  // Progressive renders count passes from 0, single renders only have pass 0
+  pub pass: u32,
+  // Tiles of this pass rendered so far, out of the ones it has left to
+  // render. A pass resumed from a checkpoint doesn't count the tiles it
+  // already had.
+  pub tiles_done: u32,
+  pub tiles_total: u32,
+  // Camera rays, secondary rays spawned inside the world aren't counted
+  pub rays_traced: u64,
+  // Time spent on this pass
+  pub elapsed: Duration,
+}
+
+impl Progress {
+  pub fn fraction(&self) -> f64 {
+    if self.tiles_total == 0 {
+      return 1.0;
+    }
+
+    self.tiles_done as f64 / self.tiles_total as f64
+  }
+
+  // Estimated time left, assuming the remaining tiles take as long as the
+  // finished ones did on average.
+  pub fn eta(&self) -> Option<Duration> {
+    if self.tiles_done == 0 {
+      return None;
+    }
+
+    Some(self.elapsed.mul_f64((self.tiles_total - self.tiles_done) as f64 / self.tiles_done as f64))
+  }
+}
+
+// Shared flag for stopping a render from another thread. Clones all refer to
+// the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+  #[allow(dead_code, reason = "main.rs has no way to interrupt a render but a time budget")]
+  pub fn new() -> CancellationToken {
+    CancellationToken::default()
+  }
+
+  #[allow(dead_code, reason = "main.rs has no way to interrupt a render but a time budget")]
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+pub type Observer = Box<dyn Fn(&Progress) + Sync>;
+
+// Everything about a render that isn't part of the camera itself.
+#[derive(Default)]
+pub struct RenderOptions {
+  pub observer: Option<Observer>,
+  pub cancellation: Option<CancellationToken>,
+  // Wall-clock time after which the render stops with what it has
+  pub time_budget: Option<Duration>,
+  // Extra buffers to render into new frames alongside the image
+  pub aovs: Vec<Aov>,
+}
+
+impl RenderOptions {
+  pub fn new() -> RenderOptions {
+    RenderOptions::default()
+  }
+
+  pub fn should_stop(&self, started: Instant) -> bool {
+    self.cancellation.as_ref().is_some_and(|c| c.is_cancelled())
+      || self.time_budget.is_some_and(|budget| started.elapsed() >= budget)
+  }
+
+  pub fn report(&self, progress: &Progress) {
+    if let Some(observer) = &self.observer {
+      observer(progress);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::progress::{CancellationToken, Progress, RenderOptions};
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn eta_extrapolates_from_finished_tiles() {
+    let p = Progress {
+      pass: 0,
+      tiles_done: 1,
+      tiles_total: 4,
+      rays_traced: 100,
+      elapsed: Duration::from_secs(2),
+    };
+
+    assert_eq!(p.eta(), Some(Duration::from_secs(6)));
+    assert_eq!(p.fraction(), 0.25);
+  }
+
+  #[test]
+  fn cancelling_a_clone_cancels_the_original() {
+    let token = CancellationToken::new();
+    let mut options = RenderOptions::new();
+    options.cancellation = Some(token.clone());
+
+    assert!(!options.should_stop(Instant::now()));
+    token.cancel();
+    assert!(options.should_stop(Instant::now()));
+  }
+
+  #[test]
+  fn an_exhausted_budget_stops_the_render() {
+    let mut options = RenderOptions::new();
+    options.time_budget = Some(Duration::from_secs(0));
+
+    assert!(options.should_stop(Instant::now()));
+  }
+}
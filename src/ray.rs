@@ -6,7 +6,10 @@ use std::ops::{Mul};
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
   pub origin: Point,
-  pub direction: Vector
+  pub direction: Vector,
+  // Moment within the shutter interval the ray was sent, moving objects are
+  // intersected where they are at that time.
+  pub time: f64,
 }
 
 impl Ray {
@@ -25,7 +28,8 @@ impl Mul<Matrix> for Ray {
   fn mul(self, rhs: Matrix) -> Ray {
     Ray {
       origin: self.origin * rhs,
-      direction: self.direction * rhs
+      direction: self.direction * rhs,
+      time: self.time,
     }
   }
 }
@@ -43,7 +47,6 @@ mod tests {
   use crate::ray::Ray;
   use crate::vector::Vector;
   use crate::point::Point;
-  use crate::sphere::Sphere;
   use crate::matrix::Matrix;
 
   #[test]
@@ -51,15 +54,16 @@ mod tests {
     let origin = Point { x: 1.0, y: 2.0, z: 3.0 };
     let direction = Vector { x: 4.0, y: 5.0, z: 6.0 };
 
-    let ray = Ray { origin, direction };
+    let ray = Ray { origin, direction, time: 0.5 };
 
     assert_eq!(ray.origin, origin);
     assert_eq!(ray.direction, direction);
+    assert_eq!(ray.time, 0.5);
   }
 
   #[test]
   fn can_compute_point_from_distance() {
-    let ray = Ray { origin: Point { x: 2.0, y: 3.0, z: 4.0 }, direction: Vector { x: 1.0, y: 0.0, z: 0.0 } };
+    let ray = Ray { origin: Point { x: 2.0, y: 3.0, z: 4.0 }, direction: Vector { x: 1.0, y: 0.0, z: 0.0 }, time: 0.0 };
 
     assert_eq!(ray.position(0.0), Point { x: 2.0, y: 3.0, z: 4.0 });
     assert_eq!(ray.position(1.0), Point { x: 3.0, y: 3.0, z: 4.0 });
@@ -69,7 +73,7 @@ mod tests {
 
   #[test]
   fn translating_a_ray() {
-    let ray = Ray { origin: Point { x: 1.0, y: 2.0, z: 3.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 } };
+    let ray = Ray { origin: Point { x: 1.0, y: 2.0, z: 3.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
     let transformation = Matrix::translate(3.0, 4.0, 5.0);
 
     let translated_ray = transformation * ray;
@@ -80,7 +84,7 @@ mod tests {
 
   #[test]
   fn scaling_a_ray() {
-    let ray = Ray { origin: Point { x: 1.0, y: 2.0, z: 3.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 } };
+    let ray = Ray { origin: Point { x: 1.0, y: 2.0, z: 3.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
     let transformation = Matrix::scale(2.0, 3.0, 4.0);
 
     let scaled_ray = transformation * ray;
@@ -88,4 +92,11 @@ mod tests {
     assert_eq!(scaled_ray.origin, Point { x: 2.0, y: 6.0, z: 12.0 });
     assert_eq!(scaled_ray.direction, Vector { x: 0.0, y: 3.0, z: 0.0 });
   }
+
+  #[test]
+  fn transforming_a_ray_keeps_its_time() {
+    let ray = Ray { origin: Point { x: 1.0, y: 2.0, z: 3.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.25 };
+
+    assert_eq!((Matrix::translate(3.0, 4.0, 5.0) * ray).time, 0.25);
+  }
 }
@@ -0,0 +1,277 @@
+use crate::canvas::Color;
+use crate::intersection::Intersection;
+use crate::material::Material;
+use crate::object::{Intersectable, Object};
+use crate::point::Point;
+use crate::point_light::PointLight;
+use crate::ray::Ray;
+use crate::rng;
+use crate::vector::Vector;
+use crate::world::World;
+use std::fmt;
+
+// Everything that happened to one ray, for debugging a single pixel.
+#[derive(Debug, Clone)]
+pub struct RayNode {
+  pub ray: Ray,
+  // Bounces left when the ray was traced
+  pub remaining: u8,
+  // Every intersection along the ray, sorted by t, including ones behind it
+  pub intersections: Vec<(f64, Object)>,
+  pub hit: Option<Hit>,
+  // What the renderer returns for this ray
+  pub color: Color,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+  pub t: f64,
+  pub object: Object,
+  pub point: Point,
+  pub normal: Vector,
+  pub inside: bool,
+  pub n1: f64,
+  pub n2: f64,
+  pub lights: Vec<LightResult>,
+  // The reflected and refracted rays are the ideal mirror and refraction
+  // directions. Rough and dispersive materials average many rays around
+  // them, which aren't listed, but the contributions include them.
+  pub reflected: Option<Box<RayNode>>,
+  pub reflected_contribution: Color,
+  pub refracted: Option<Box<RayNode>>,
+  pub refracted_contribution: Color,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LightResult {
+  pub light: PointLight,
+  pub in_shadow: bool,
+  pub contribution: Color,
+}
+
+impl World {
+  // The tree of a ray leaving the camera.
+  pub fn ray_tree(&self, r: Ray, remaining: u8) -> RayNode {
+    self.ray_tree_through(r, None, remaining, true)
+  }
+
+  // Colours and contributions come from the renderer's own shading, run
+  // from the same random numbers as the renderer, so they are the values
+  // it uses. The reflected and refracted children are traced on their own
+  // and only explain where that light came from.
+  fn ray_tree_through(&self, r: Ray, medium: Option<Material>, remaining: u8, primary: bool) -> RayNode {
+    let intersections = self.intersect(r);
+
+    let hit = Intersection::hit(intersections.clone()).map(|hit| {
+      let comps = hit.prepare_computations_with_intersections(r, intersections.clone());
+      let material = comps.object.material();
+      let black = Color { r: 0.0, g: 0.0, b: 0.0 };
+      let mut lights = Vec::new();
+
+      // Holdouts are black to every ray, so nothing is traced from them
+      let visible = !hit.object.holdout();
+      let (reflected_contribution, refracted_contribution) = if visible {
+        let (_, reflected, refracted) = rng::isolated(|| {
+          self.shading(&comps, remaining, |light, in_shadow, contribution| lights.push(LightResult { light, in_shadow, contribution }))
+        });
+
+        (reflected, refracted)
+      } else {
+        (black, black)
+      };
+
+      let reflect_ray = Ray { origin: comps.over_point, direction: comps.reflect_vector, time: comps.ray_time };
+      let reflected = (visible && remaining > 0 && material.reflective != 0.0).then(|| self.child(reflect_ray, comps.n1_medium, remaining - 1));
+      let refracted = if visible && remaining > 0 && material.transparency != 0.0 {
+        World::refraction_ray(&comps, comps.n1, comps.n2).map(|refract_ray| self.child(refract_ray, comps.n2_medium, remaining - 1))
+      } else {
+        None
+      };
+
+      Hit {
+        t: hit.time,
+        object: *hit.object,
+        point: comps.point,
+        normal: comps.normal,
+        inside: comps.inside,
+        n1: comps.n1,
+        n2: comps.n2,
+        lights,
+        reflected,
+        reflected_contribution,
+        refracted,
+        refracted_contribution,
+      }
+    });
+
+    let color = if primary {
+      self.trace_primary(r, remaining).0
+    } else {
+      self.color_through(r, medium, remaining)
+    };
+
+    RayNode {
+      ray: r,
+      remaining,
+      intersections: intersections.iter().map(|i| (i.time, *i.object)).collect(),
+      hit,
+      color,
+    }
+  }
+
+  fn child(&self, r: Ray, medium: Option<Material>, remaining: u8) -> Box<RayNode> {
+    Box::new(rng::isolated(|| self.ray_tree_through(r, medium, remaining, false)))
+  }
+}
+
+impl RayNode {
+  fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    let c = self.color;
+
+    writeln!(f, "{}ray {:?} -> {:?}, {} bounces left, color ({:.5}, {:.5}, {:.5})", indent, self.ray.origin, self.ray.direction, self.remaining, c.r, c.g, c.b)?;
+    writeln!(f, "{}  intersections at {:?}", indent, self.intersections.iter().map(|(t, _)| *t).collect::<Vec<f64>>())?;
+
+    let hit = match &self.hit {
+      Some(hit) => hit,
+      None => return writeln!(f, "{}  miss", indent),
+    };
+
+    let shape = match hit.object {
+      Object::Sphere(_) => "sphere",
+      Object::Plane(_) => "plane",
+      Object::Cube(_) => "cube",
+    };
+    writeln!(f, "{}  hit {} at t = {:.5}, {:?}, normal {:?}, n1 = {}, n2 = {}{}", indent, shape, hit.t, hit.point, hit.normal, hit.n1, hit.n2, if hit.inside { ", inside" } else { "" })?;
+    for (i, light) in hit.lights.iter().enumerate() {
+      let c = light.contribution;
+      writeln!(f, "{}  light {} at {:?}: {}, ({:.5}, {:.5}, {:.5})", indent, i, light.light.position, if light.in_shadow { "shadowed" } else { "lit" }, c.r, c.g, c.b)?;
+    }
+
+    for (name, child, c) in [("reflected", &hit.reflected, hit.reflected_contribution), ("refracted", &hit.refracted, hit.refracted_contribution)] {
+      if let Some(child) = child {
+        writeln!(f, "{}  {} ({:.5}, {:.5}, {:.5}):", indent, name, c.r, c.g, c.b)?;
+        child.write(f, depth + 2)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl fmt::Display for RayNode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.write(f, 0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::Color;
+  use crate::plane::Plane;
+  use crate::point::Point;
+  use crate::ray::Ray;
+  use crate::vector::Vector;
+  use crate::world::World;
+  use crate::object::Object;
+  use crate::matrix::Matrix;
+  use crate::sphere::Sphere;
+  use crate::medium::Medium;
+  use crate::rng;
+
+  fn ray(z: f64) -> Ray {
+    Ray {
+      origin: Point { x: 0.0, y: 0.0, z },
+      direction: Vector { x: 0.0, y: 0.0, z: 1.0 },
+      time: 0.0,
+    }
+  }
+
+  #[test]
+  fn tree_for_the_default_world_matches_color_at() {
+    let w = World::default();
+    let tree = w.ray_tree(ray(-5.0), 5);
+
+    assert_eq!(tree.color, w.color_at(ray(-5.0), 5));
+    assert_eq!(tree.intersections.iter().map(|(t, _)| *t).collect::<Vec<f64>>(), vec![4.0, 4.5, 5.5, 6.0]);
+
+    let hit = tree.hit.unwrap();
+    assert_eq!(hit.t, 4.0);
+    assert_eq!((hit.n1, hit.n2), (1.0, 1.0));
+    assert!(!hit.lights[0].in_shadow);
+    assert_eq!(hit.lights[0].contribution, tree.color);
+    assert!(hit.reflected.is_none());
+    assert!(hit.refracted.is_none());
+  }
+
+  #[test]
+  fn trees_draw_the_same_random_numbers_as_the_renderer() {
+    let mut w = World::default();
+    w.fog = Some(Medium::new(0.02, 0.05));
+    if let Object::Sphere(ref mut s) = w.objects[0] {
+      s.material.reflective = 0.5;
+      s.material.roughness = 0.3;
+    }
+    let mut floor = Plane::new();
+    floor.transform = Matrix::translate(0.0, -1.0, 0.0);
+    floor.material.reflective = 0.5;
+    w.objects.push(Object::Plane(floor));
+    let r = Ray {
+      origin: Point { x: 0.0, y: 0.0, z: -5.0 },
+      direction: Vector { x: 0.0, y: -0.3, z: 1.0 }.normalize(),
+      time: 0.0,
+    };
+
+    rng::seed(9);
+    let expected = w.color_at(r, 5);
+    rng::seed(9);
+    let tree = w.ray_tree(r, 5);
+
+    assert_eq!(tree.color, expected);
+    assert!(tree.hit.unwrap().reflected.is_some());
+  }
+
+  #[test]
+  fn shadowed_lights_are_reported() {
+    let mut w = World::default();
+    let mut floor = Plane::new();
+    floor.transform = Matrix::translate(0.0, -1.0, 0.0);
+    w.objects.push(Object::Plane(floor));
+
+    // The floor under the spheres, on the far side from the light
+    let r = Ray {
+      origin: Point { x: 1.0, y: 5.0, z: 1.0 },
+      direction: Vector { x: 0.0, y: -1.0, z: 0.0 },
+      time: 0.0,
+    };
+    let hit = w.ray_tree(r, 5).hit.unwrap();
+
+    assert_eq!(hit.point, Point { x: 1.0, y: -1.0, z: 1.0 });
+    assert!(hit.lights[0].in_shadow);
+  }
+
+  #[test]
+  fn glass_spawns_reflected_and_refracted_rays() {
+    let mut w = World::default();
+    let mut glass = Sphere::new();
+    glass.material.reflective = 0.5;
+    glass.material.transparency = 1.0;
+    glass.material.refractive_index = 1.5;
+    w.objects = vec![Object::Sphere(glass)];
+
+    let tree = w.ray_tree(ray(-5.0), 2);
+    let hit = tree.hit.unwrap();
+
+    assert_eq!((hit.n1, hit.n2), (1.0, 1.5));
+    let refracted = hit.refracted.unwrap();
+    let inner = refracted.hit.unwrap();
+    assert_eq!((inner.n1, inner.n2), (1.5, 1.0));
+    assert!(inner.inside);
+    assert_eq!(refracted.remaining, 1);
+
+    let reflected = hit.reflected.unwrap();
+    assert!(reflected.hit.is_none());
+    assert_eq!(hit.reflected_contribution, Color { r: 0.0, g: 0.0, b: 0.0 });
+    assert!(format!("{}", w.ray_tree(ray(-5.0), 2)).contains("refracted"));
+  }
+}
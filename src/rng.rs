@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+
+// Small xorshift64* generator. Good enough for jittering rays, and its whole
+// state is a single u64 so it is cheap to reseed and easy to store.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Rng {
+  pub state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    // Run the seed through splitmix64 so that nearby seeds (pixel coordinates)
+    // don't produce correlated sequences, and so the state is never zero.
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    Rng { state: if z == 0 { 1 } else { z } }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+
+    self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  // Uniform in [0, 1)
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+}
+
+thread_local! {
+  static THREAD_RNG: RefCell<Rng> = RefCell::new(Rng::new(0));
+}
+
+pub fn seed(seed: u64) {
+  THREAD_RNG.with(|rng| *rng.borrow_mut() = Rng::new(seed));
+}
+
+pub fn random() -> f64 {
+  THREAD_RNG.with(|rng| rng.borrow_mut().next_f64())
+}
+
+// Runs `f` and then puts the thread RNG back the way it was, so whatever `f`
+// draws doesn't change the numbers that come after.
+pub fn isolated<T>(f: impl FnOnce() -> T) -> T {
+  let saved = THREAD_RNG.with(|rng| *rng.borrow());
+  let value = f();
+  THREAD_RNG.with(|rng| *rng.borrow_mut() = saved);
+
+  value
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::rng::{isolated, random, seed, Rng};
+
+  #[test]
+  fn same_seed_gives_same_sequence() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+
+    for _ in 0..10 {
+      assert_eq!(a.next_u64(), b.next_u64());
+    }
+  }
+
+  #[test]
+  fn values_are_in_unit_interval() {
+    let mut r = Rng::new(7);
+
+    for _ in 0..1000 {
+      let v = r.next_f64();
+      assert!((0.0..1.0).contains(&v));
+    }
+  }
+
+  #[test]
+  fn reseeding_the_thread_rng_restarts_the_sequence() {
+    seed(3);
+    let a = random();
+    seed(3);
+    let b = random();
+
+    assert_eq!(a, b);
+  }
+
+  #[test]
+  fn isolated_draws_leave_the_sequence_alone() {
+    seed(5);
+    let a = random();
+    seed(5);
+    isolated(|| (random(), random()));
+    let b = random();
+
+    assert_eq!(a, b);
+  }
+}
@@ -0,0 +1,250 @@
+use crate::rng::random;
+
+// Where inside a pixel the camera sends its rays. Every sampler hands out
+// offsets in the unit square, randomised from the thread RNG so that
+// neighbouring pixels don't share the same pattern.
+#[derive(PartialEq, Debug, Copy, Clone)]
+#[allow(dead_code, reason = "the demo scene in main.rs only uses a grid")]
+pub enum Sampler {
+  // One ray through the middle of the pixel
+  Center,
+  // Regular n x n grid
+  Grid(u32),
+  // n x n grid, jittered inside each cell
+  Stratified(u32),
+  // Independent uniform samples
+  Random(u32),
+  // Low-discrepancy sequences, randomly shifted per pixel
+  Halton(u32),
+  Sobol(u32),
+  // Kensler's correlated multi-jittered sampling
+  MultiJitter(u32),
+}
+
+// Keeps asking the sampler for more batches of samples until the standard
+// error of the pixel's luminance drops below `threshold`, taking at least
+// `min_samples` and stopping once `max_samples` is reached.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct AdaptiveSampling {
+  pub min_samples: u32,
+  pub max_samples: u32,
+  pub threshold: f64,
+}
+
+impl Sampler {
+  pub fn samples_per_pixel(&self) -> u32 {
+    match *self {
+      Sampler::Center => 1,
+      Sampler::Grid(n) | Sampler::Stratified(n) => n.max(1) * n.max(1),
+      Sampler::Random(n) | Sampler::Halton(n) | Sampler::Sobol(n) | Sampler::MultiJitter(n) => n.max(1),
+    }
+  }
+
+  pub fn offsets(&self) -> Vec<(f64, f64)> {
+    let count = self.samples_per_pixel();
+
+    match *self {
+      Sampler::Center => vec![(0.5, 0.5)],
+      Sampler::Grid(n) | Sampler::Stratified(n) => {
+        let n = n.max(1);
+        let jittered = matches!(*self, Sampler::Stratified(_));
+        let mut offsets = Vec::with_capacity(count as usize);
+
+        for j in 0..n {
+          for i in 0..n {
+            let (jx, jy) = if jittered { (random(), random()) } else { (0.5, 0.5) };
+            offsets.push(((i as f64 + jx) / n as f64, (j as f64 + jy) / n as f64));
+          }
+        }
+
+        offsets
+      }
+      Sampler::Random(_) => (0..count).map(|_| (random(), random())).collect(),
+      Sampler::Halton(_) => {
+        let (sx, sy) = (random(), random());
+
+        (0..count).map(|i| ((radical_inverse(i + 1, 2) + sx).fract(), (radical_inverse(i + 1, 3) + sy).fract())).collect()
+      }
+      Sampler::Sobol(_) => {
+        let (sx, sy) = (scramble(), scramble());
+
+        (0..count).map(|i| sobol(i, sx, sy)).collect()
+      }
+      Sampler::MultiJitter(_) => {
+        let pattern = scramble();
+
+        (0..count).map(|i| multi_jitter(i, count, pattern)).collect()
+      }
+    }
+  }
+}
+
+fn scramble() -> u32 {
+  (random() * u32::MAX as f64) as u32
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f64 {
+  let inverse_base = 1.0 / base as f64;
+  let mut factor = inverse_base;
+  let mut result = 0.0;
+
+  while i > 0 {
+    result += (i % base) as f64 * factor;
+    i /= base;
+    factor *= inverse_base;
+  }
+
+  result
+}
+
+// First two Sobol dimensions with XOR scrambling (Kollig & Keller).
+fn sobol(i: u32, sx: u32, sy: u32) -> (f64, f64) {
+  let x = i.reverse_bits() ^ sx;
+
+  let mut y = sy;
+  let mut v: u32 = 1 << 31;
+  let mut bits = i;
+  while bits != 0 {
+    if bits & 1 != 0 {
+      y ^= v;
+    }
+    bits >>= 1;
+    v ^= v >> 1;
+  }
+
+  (x as f64 / 4_294_967_296.0, y as f64 / 4_294_967_296.0)
+}
+
+// "Correlated Multi-Jittered Sampling", Andrew Kensler, 2013.
+fn multi_jitter(s: u32, count: u32, p: u32) -> (f64, f64) {
+  let m = (count as f64).sqrt().round().max(1.0) as u32;
+  let n = count.div_ceil(m);
+
+  let sx = permute(s % m, m, p.wrapping_mul(0xa511_e9b3));
+  let sy = permute(s / m, n, p.wrapping_mul(0x63d8_3595));
+  let jx = hash_float(s, p.wrapping_mul(0xa399_d265));
+  let jy = hash_float(s, p.wrapping_mul(0x711a_d6a5));
+
+  (
+    ((s % m) as f64 + (sy as f64 + jx) / n as f64) / m as f64,
+    ((s / m) as f64 + (sx as f64 + jy) / m as f64) / n as f64,
+  )
+}
+
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+  let mut w = l.wrapping_sub(1);
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+
+  loop {
+    i ^= p;
+    i = i.wrapping_mul(0xe170_893d);
+    i ^= p >> 16;
+    i ^= (i & w) >> 4;
+    i ^= p >> 8;
+    i = i.wrapping_mul(0x0929_eb3f);
+    i ^= p >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | p >> 27);
+    i = i.wrapping_mul(0x6935_fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dc_b303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e50_1cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860_a3df);
+    i &= w;
+    i ^= i >> 5;
+
+    if i < l {
+      break;
+    }
+  }
+
+  (i.wrapping_add(p)) % l
+}
+
+fn hash_float(mut i: u32, p: u32) -> f64 {
+  i ^= p;
+  i ^= i >> 17;
+  i ^= i >> 10;
+  i = i.wrapping_mul(0xb365_34e5);
+  i ^= i >> 12;
+  i ^= i >> 21;
+  i = i.wrapping_mul(0x93fc_4795);
+  i ^= 0xdf6e_307f;
+  i ^= i >> 17;
+  i = i.wrapping_mul(1 | p >> 18);
+
+  i as f64 / 4_294_967_808.0
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::rng::seed;
+  use crate::sampler::Sampler;
+
+  fn all_samplers() -> Vec<Sampler> {
+    vec![
+      Sampler::Center,
+      Sampler::Grid(3),
+      Sampler::Stratified(3),
+      Sampler::Random(9),
+      Sampler::Halton(9),
+      Sampler::Sobol(9),
+      Sampler::MultiJitter(9),
+    ]
+  }
+
+  #[test]
+  fn samplers_hand_out_samples_per_pixel_offsets_inside_the_pixel() {
+    seed(1);
+
+    for s in all_samplers() {
+      let offsets = s.offsets();
+
+      assert_eq!(offsets.len() as u32, s.samples_per_pixel());
+      for (u, v) in offsets {
+        assert!((0.0..1.0).contains(&u), "{:?} gave u = {}", s, u);
+        assert!((0.0..1.0).contains(&v), "{:?} gave v = {}", s, v);
+      }
+    }
+  }
+
+  #[test]
+  fn a_two_by_two_grid_matches_the_old_antialiasing() {
+    assert_eq!(Sampler::Grid(2).offsets(), vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+  }
+
+  #[test]
+  fn stratified_samples_fall_one_per_cell() {
+    seed(2);
+    let offsets = Sampler::Stratified(4).offsets();
+
+    for j in 0..4 {
+      for i in 0..4 {
+        let (u, v) = offsets[j * 4 + i];
+
+        assert_eq!((u * 4.0) as usize, i);
+        assert_eq!((v * 4.0) as usize, j);
+      }
+    }
+  }
+
+  #[test]
+  fn multi_jitter_is_stratified_in_both_dimensions() {
+    seed(3);
+    let offsets = Sampler::MultiJitter(16).offsets();
+
+    let mut columns: Vec<usize> = offsets.iter().map(|(u, _)| (u * 16.0) as usize).collect();
+    let mut rows: Vec<usize> = offsets.iter().map(|(_, v)| (v * 16.0) as usize).collect();
+    columns.sort();
+    rows.sort();
+
+    assert_eq!(columns, (0..16).collect::<Vec<usize>>());
+    assert_eq!(rows, (0..16).collect::<Vec<usize>>());
+  }
+}
@@ -9,8 +9,13 @@ use crate::object::{Object,Intersectable};
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Sphere {
   pub transform: Matrix,
+  // Where the shape has moved to when the shutter closes, if it moves.
+  pub end_transform: Option<Matrix>,
   pub material: Material,
   pub casts_shadow: bool,
+  // Hidden from the camera and cut out of the alpha channel, but still
+  // blocks and shadows everything else. Mirrors and glass see it as black.
+  pub holdout: bool,
 }
 
 impl Intersectable for Sphere {
@@ -23,7 +28,7 @@ impl Intersectable for Sphere {
   }
 
   fn intersect(&self, r: Ray) -> Vec<f64> {
-    let ray2 = r * self.transform.inverse().unwrap();
+    let ray2 = r * self.transform_at(r.time).inverse().unwrap();
     let sphere_to_ray = ray2.origin - Point { x: 0.0, y: 0.0, z: 0.0 };
 
     let a = ray2.direction.dot(&ray2.direction);
@@ -50,17 +55,30 @@ impl Intersectable for Sphere {
     self.transform
   }
 
+  fn transform_at(&self, time: f64) -> Matrix {
+    match self.end_transform {
+      Some(end) => self.transform.interpolate(&end, time),
+      None => self.transform,
+    }
+  }
+
   fn casts_shadow(&self) -> bool {
     self.casts_shadow
   }
+
+  fn holdout(&self) -> bool {
+    self.holdout
+  }
 }
 
 impl Sphere {
   pub fn new() -> Sphere {
     Sphere {
       transform: Matrix::identity(),
+      end_transform: None,
       material: Material::new(),
       casts_shadow: true,
+      holdout: false,
     }
   }
 
@@ -82,7 +100,7 @@ mod tests {
 
   #[test]
   fn insersects_sphere_at_two_points() {
-    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let sphere = Sphere::new();
 
     let intersections = sphere.intersect(ray);
@@ -94,7 +112,7 @@ mod tests {
 
   #[test]
   fn insersects_sphere_at_tangent() {
-    let ray = Ray { origin: Point { x: 0.0, y: 1.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 1.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let sphere = Sphere::new();
 
     let intersections = sphere.intersect(ray);
@@ -106,7 +124,7 @@ mod tests {
 
   #[test]
   fn ray_originates_inside_sphere() {
-    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let sphere = Sphere::new();
 
     let intersections = sphere.intersect(ray);
@@ -118,7 +136,7 @@ mod tests {
 
   #[test]
   fn ray_is_behind_sphere() {
-    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let sphere = Sphere::new();
 
     let intersections = sphere.intersect(ray);
@@ -130,7 +148,7 @@ mod tests {
 
   // #[test]
   // fn intersect_sets_the_object_on_intersection() {
-  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+  //   let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
   //   let sphere = Sphere::new();
 
   //   let intersections = sphere.intersect(ray);
@@ -160,7 +178,7 @@ mod tests {
 
   #[test]
   fn intersecting_a_scaled_sphere_with_a_ray() {
-    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let mut sphere = Sphere::new();
     sphere.set_transform(Matrix::scale(2.0, 2.0, 2.0));
 
@@ -172,7 +190,7 @@ mod tests {
 
   #[test]
   fn intersecting_a_translated_sphere_with_a_ray() {
-    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let ray = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let mut sphere = Sphere::new();
     sphere.set_transform(Matrix::translate(5.0, 0.0, 0.0));
 
@@ -208,15 +226,15 @@ mod tests {
   #[test]
   fn normal_at_a_nonaxial_point() {
     let s = Sphere::new();
-    let n = s.normal(Point { x: (3.0 as f64).sqrt() / 3.0, y: (3.0 as f64).sqrt() / 3.0, z: (3.0 as f64).sqrt() / 3.0 });
+    let n = s.normal(Point { x: 3.0_f64.sqrt() / 3.0, y: 3.0_f64.sqrt() / 3.0, z: 3.0_f64.sqrt() / 3.0 });
 
-    assert_eq!(n, Vector { x: (3.0 as f64).sqrt() / 3.0, y: (3.0 as f64).sqrt() / 3.0, z: (3.0 as f64).sqrt() / 3.0 });
+    assert_eq!(n, Vector { x: 3.0_f64.sqrt() / 3.0, y: 3.0_f64.sqrt() / 3.0, z: 3.0_f64.sqrt() / 3.0 });
   }
 
   #[test]
   fn normal_is_normalized() {
     let s = Sphere::new();
-    let n = s.normal(Point { x: (3.0 as f64).sqrt() / 3.0, y: (3.0 as f64).sqrt() / 3.0, z: (3.0 as f64).sqrt() / 3.0 });
+    let n = s.normal(Point { x: 3.0_f64.sqrt() / 3.0, y: 3.0_f64.sqrt() / 3.0, z: 3.0_f64.sqrt() / 3.0 });
 
     assert_eq!(n, n.normalize());
   }
@@ -237,7 +255,7 @@ mod tests {
     let transform = Matrix::scale(1.0, 0.5, 1.0) * Matrix::rotate_z(std::f64::consts::PI / 5.0);
     s.set_transform(transform);
 
-    let n = s.normal(Point { x: 0.0, y: (2.0 as f64).sqrt() / 2.0, z: -((2.0 as f64).sqrt() / 2.0) });
+    let n = s.normal(Point { x: 0.0, y: 2.0_f64.sqrt() / 2.0, z: -(2.0_f64.sqrt() / 2.0) });
 
     assert_eq!(n, Vector { x: 0.0, y: 0.97014, z: -0.24254 });
   }
@@ -254,10 +272,35 @@ mod tests {
     let mut s = Sphere::new();
     let mut m = Material::new();
     m.ambient = 1.0;
-    s.material = m.clone();
+    s.material = m;
 
-    let cm = Material { color: Color { r: 1.0, g: 1.0, b: 1.0 }, ambient: 1.0, diffuse: 0.9, specular: 0.9, shininess: 200.0, pattern: None, reflective: 0.0, refractive_index: 1.0, transparency: 0.0 };
+    let cm = Material { color: Color { r: 1.0, g: 1.0, b: 1.0 }, ambient: 1.0, diffuse: 0.9, specular: 0.9, shininess: 200.0, pattern: None, reflective: 0.0, refractive_index: 1.0, transparency: 0.0, roughness: 0.0, glossy_samples: 16, absorption_color: Color { r: 1.0, g: 1.0, b: 1.0 }, absorption_density: 0.0, dispersion: 0.0, volume: None };
 
     assert_eq!(s.material, cm);
   }
+
+  #[test]
+  fn intersecting_a_moving_sphere_depends_on_ray_time() {
+    let mut sphere = Sphere::new();
+    sphere.end_transform = Some(Matrix::translate(5.0, 0.0, 0.0));
+
+    let early = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+    let late = Ray { time: 1.0, ..early };
+    let halfway = Ray { origin: Point { x: 2.5, y: 0.0, z: -5.0 }, time: 0.5, ..early };
+
+    assert_eq!(sphere.intersect(early), vec![4.0, 6.0]);
+    assert_eq!(sphere.intersect(late).len(), 0);
+    assert_eq!(sphere.intersect(halfway), vec![4.0, 6.0]);
+  }
+
+  #[test]
+  fn a_moving_sphere_can_be_frozen_in_time() {
+    let mut sphere = Sphere::new();
+    sphere.end_transform = Some(Matrix::translate(0.0, 2.0, 0.0));
+
+    let frozen = Object::Sphere(sphere).at_time(0.5);
+
+    assert_eq!(frozen.transform(), Matrix::translate(0.0, 1.0, 0.0));
+    assert_eq!(frozen.normal(Point { x: 0.0, y: 2.0, z: 0.0 }), Vector { x: 0.0, y: 1.0, z: 0.0 });
+  }
 }
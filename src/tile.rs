@@ -0,0 +1,188 @@
+// Rectangular block of pixels rendered as one unit of work. Tiles along the
+// right and bottom edges are clipped to the image.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Tile {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Tile {
+  pub fn contains(&self, x: u32, y: u32) -> bool {
+    x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+  }
+
+  pub fn overlaps(&self, other: &Tile) -> bool {
+    self.x < other.x + other.width && other.x < self.x + self.width && self.y < other.y + other.height && other.y < self.y + self.height
+  }
+}
+
+// The order tiles are handed out to the render threads in.
+#[derive(PartialEq, Debug, Copy, Clone)]
+#[allow(dead_code, reason = "main.rs renders in the default spiral order")]
+pub enum TileOrder {
+  // Left to right, top to bottom
+  Scanline,
+  // Outwards from the centre of the image, where the subject usually is
+  Spiral,
+  // Along a Hilbert curve, so consecutive tiles are always neighbours
+  Hilbert,
+}
+
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+  let size = size.max(1);
+  let columns = width.div_ceil(size) as i64;
+  let rows = height.div_ceil(size) as i64;
+
+  let cells: Vec<(i64, i64)> = match order {
+    TileOrder::Scanline => (0..rows).flat_map(|r| (0..columns).map(move |c| (c, r))).collect(),
+    TileOrder::Spiral => spiral(columns, rows),
+    TileOrder::Hilbert => hilbert(columns, rows),
+  };
+
+  cells
+    .into_iter()
+    .map(|(c, r)| {
+      let x = c as u32 * size;
+      let y = r as u32 * size;
+
+      Tile {
+        x,
+        y,
+        width: size.min(width - x),
+        height: size.min(height - y),
+      }
+    })
+    .collect()
+}
+
+// Square spiral walked out from the centre cell, skipping cells that fall
+// outside the grid.
+fn spiral(columns: i64, rows: i64) -> Vec<(i64, i64)> {
+  let total = (columns * rows) as usize;
+  let mut cells = Vec::with_capacity(total);
+  let (mut c, mut r) = ((columns - 1) / 2, (rows - 1) / 2);
+  let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+  let mut leg = 0;
+
+  while cells.len() < total {
+    let (dc, dr) = directions[leg % 4];
+    let length = leg / 2 + 1;
+
+    for _ in 0..length {
+      if (0..columns).contains(&c) && (0..rows).contains(&r) {
+        cells.push((c, r));
+      }
+      c += dc;
+      r += dr;
+    }
+
+    leg += 1;
+  }
+
+  cells
+}
+
+// Hilbert curve over the smallest power of two square covering the grid.
+fn hilbert(columns: i64, rows: i64) -> Vec<(i64, i64)> {
+  let n = (columns.max(rows).max(1) as u64).next_power_of_two() as i64;
+
+  (0..n * n)
+    .map(|d| hilbert_cell(n, d))
+    .filter(|&(c, r)| c < columns && r < rows)
+    .collect()
+}
+
+fn hilbert_cell(n: i64, d: i64) -> (i64, i64) {
+  let (mut x, mut y) = (0, 0);
+  let mut t = d;
+  let mut s = 1;
+
+  while s < n {
+    let rx = 1 & (t / 2);
+    let ry = 1 & (t ^ rx);
+
+    if ry == 0 {
+      if rx == 1 {
+        x = s - 1 - x;
+        y = s - 1 - y;
+      }
+      std::mem::swap(&mut x, &mut y);
+    }
+
+    x += s * rx;
+    y += s * ry;
+    t /= 4;
+    s *= 2;
+  }
+
+  (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::tile::{tiles, Tile, TileOrder};
+
+  fn covered(tiles: &[Tile], width: u32, height: u32) -> Vec<u32> {
+    let mut counts = vec![0; (width * height) as usize];
+
+    for t in tiles {
+      for y in t.y..t.y + t.height {
+        for x in t.x..t.x + t.width {
+          counts[(y * width + x) as usize] += 1;
+        }
+      }
+    }
+
+    counts
+  }
+
+  #[test]
+  fn every_order_covers_each_pixel_exactly_once() {
+    for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+      let t = tiles(70, 45, 16, order);
+
+      assert_eq!(t.len(), 5 * 3);
+      assert!(covered(&t, 70, 45).iter().all(|&c| c == 1), "{:?}", order);
+    }
+  }
+
+  #[test]
+  fn edge_tiles_are_clipped_to_the_image() {
+    let t = tiles(70, 45, 16, TileOrder::Scanline);
+
+    assert_eq!(t[4], Tile { x: 64, y: 0, width: 6, height: 16 });
+    assert_eq!(t[14], Tile { x: 64, y: 32, width: 6, height: 13 });
+  }
+
+  #[test]
+  fn tiles_overlap_when_they_share_a_pixel() {
+    let a = Tile { x: 0, y: 0, width: 4, height: 4 };
+
+    assert!(a.overlaps(&Tile { x: 3, y: 3, width: 4, height: 4 }));
+    assert!(!a.overlaps(&Tile { x: 4, y: 0, width: 4, height: 4 }));
+    assert!(a.contains(3, 3));
+    assert!(!a.contains(4, 3));
+  }
+
+  #[test]
+  fn spiral_starts_in_the_middle() {
+    let t = tiles(80, 48, 16, TileOrder::Spiral);
+
+    assert_eq!((t[0].x, t[0].y), (32, 16));
+    assert_eq!((t[1].x, t[1].y), (48, 16));
+    assert_eq!((t[2].x, t[2].y), (48, 32));
+  }
+
+  #[test]
+  fn consecutive_hilbert_tiles_are_neighbours() {
+    let t = tiles(128, 128, 16, TileOrder::Hilbert);
+
+    for pair in t.windows(2) {
+      let distance = (pair[0].x as i64 - pair[1].x as i64).abs() + (pair[0].y as i64 - pair[1].y as i64).abs();
+
+      assert_eq!(distance, 16);
+    }
+  }
+}
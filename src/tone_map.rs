@@ -0,0 +1,112 @@
+use crate::canvas::Color;
+
+// Maps linear HDR colour into the 0..1 range that fits in an image file.
+#[derive(PartialEq, Debug, Copy, Clone)]
+#[allow(dead_code, reason = "main.rs saves with the default clamp")]
+pub enum ToneMap {
+  // Cuts everything above 1 off
+  Clamp,
+  // L / (1 + L) on luminance, never quite reaches white
+  Reinhard,
+  // Reinhard with luminance `white` and above mapped to pure white
+  ReinhardExtended { white: f64 },
+  // John Hable's filmic curve from Uncharted 2
+  Hable,
+  // Krzysztof Narkowicz's fit of the ACES reference rendering transform
+  Aces,
+}
+
+impl ToneMap {
+  pub fn apply(&self, c: Color) -> Color {
+    match *self {
+      ToneMap::Clamp => per_channel(c, |x| x.clamp(0.0, 1.0)),
+      ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+      ToneMap::ReinhardExtended { white } => scale_luminance(c, |l| l * (1.0 + l / (white * white)) / (1.0 + l)),
+      ToneMap::Hable => {
+        let exposure_bias = 2.0;
+        let white_scale = 1.0 / hable(11.2);
+
+        per_channel(c, |x| (hable(x * exposure_bias) * white_scale).clamp(0.0, 1.0))
+      }
+      ToneMap::Aces => per_channel(c, |x| ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)),
+    }
+  }
+}
+
+fn per_channel(c: Color, f: impl Fn(f64) -> f64) -> Color {
+  Color {
+    r: f(c.r.max(0.0)),
+    g: f(c.g.max(0.0)),
+    b: f(c.b.max(0.0)),
+  }
+}
+
+// Maps the luminance and scales the colour to match, which keeps the hue.
+fn scale_luminance(c: Color, f: impl Fn(f64) -> f64) -> Color {
+  let l = c.luminance();
+
+  if l <= 0.0 {
+    return Color { r: 0.0, g: 0.0, b: 0.0 };
+  }
+
+  per_channel(c * (f(l) / l), |x| x.min(1.0))
+}
+
+fn hable(x: f64) -> f64 {
+  let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+
+  ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::canvas::Color;
+  use crate::tone_map::ToneMap;
+  use crate::utils::equal;
+
+  fn grey(v: f64) -> Color {
+    Color { r: v, g: v, b: v }
+  }
+
+  #[test]
+  fn clamp_keeps_displayable_colours_as_they_are() {
+    assert_eq!(ToneMap::Clamp.apply(Color { r: 0.2, g: 0.4, b: 0.6 }), Color { r: 0.2, g: 0.4, b: 0.6 });
+    assert_eq!(ToneMap::Clamp.apply(Color { r: 3.0, g: -1.0, b: 0.5 }), Color { r: 1.0, g: 0.0, b: 0.5 });
+  }
+
+  #[test]
+  fn reinhard_halves_a_luminance_of_one() {
+    assert_eq!(ToneMap::Reinhard.apply(grey(1.0)), grey(0.5));
+    assert!(ToneMap::Reinhard.apply(grey(1000.0)).r < 1.0);
+  }
+
+  #[test]
+  fn extended_reinhard_maps_the_white_point_to_white() {
+    assert_eq!(ToneMap::ReinhardExtended { white: 4.0 }.apply(grey(4.0)), grey(1.0));
+    assert_eq!(ToneMap::ReinhardExtended { white: 4.0 }.apply(grey(8.0)), grey(1.0));
+  }
+
+  #[test]
+  fn filmic_curves_are_monotonic_and_stay_in_range() {
+    for t in [ToneMap::Hable, ToneMap::Aces] {
+      assert!(equal(t.apply(grey(0.0)).r, 0.0));
+
+      let mut previous = 0.0;
+      for i in 1..100 {
+        let v = t.apply(grey(i as f64 * 0.25)).r;
+
+        assert!(v >= previous, "{:?}", t);
+        assert!(v <= 1.0, "{:?}", t);
+        previous = v;
+      }
+    }
+  }
+
+  #[test]
+  fn reinhard_keeps_the_hue() {
+    let c = ToneMap::Reinhard.apply(Color { r: 2.0, g: 1.0, b: 0.5 });
+
+    assert!(equal(c.r / c.g, 2.0));
+    assert!(equal(c.g / c.b, 2.0));
+  }
+}
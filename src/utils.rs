@@ -5,3 +5,7 @@ pub fn equal(a: f64, b: f64) -> bool {
 
   false
 }
+
+pub fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+  s.parse().map_err(|_| format!("{} is not a valid number", s))
+}
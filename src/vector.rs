@@ -1,5 +1,6 @@
 use std::ops::{Add, Sub, Neg, Mul, Div};
 use crate::utils::equal;
+use crate::rng::random;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vector {
@@ -37,6 +38,20 @@ impl Vector {
   pub fn reflect(&self, normal: Vector) -> Vector {
     *self - normal * 2.0 * self.dot(&normal)
   }
+
+  // Nudges a unit vector by a random offset inside a sphere of radius
+  // `amount`, giving a lobe of directions around the original one.
+  pub fn jitter(&self, amount: f64) -> Vector {
+    let offset = loop {
+      let v = Vector { x: random() * 2.0 - 1.0, y: random() * 2.0 - 1.0, z: random() * 2.0 - 1.0 };
+
+      if v.dot(&v) <= 1.0 {
+        break v;
+      }
+    };
+
+    (*self + offset * amount).normalize()
+  }
 }
 
 impl PartialEq for Vector {
@@ -254,10 +269,29 @@ mod tests {
   #[test]
   fn reflect_a_vector_on_slanted_surface() {
     let v = Vector { x: 0.0, y: -1.0, z: 0.0 };
-    let n = Vector { x: (2.0 as f64).sqrt() / 2.0, y: (2.0 as f64).sqrt() / 2.0, z: 0.0 };
+    let n = Vector { x: 2.0_f64.sqrt() / 2.0, y: 2.0_f64.sqrt() / 2.0, z: 0.0 };
 
     let r = v.reflect(n);
 
     assert_eq!(r, Vector { x: 1.0, y: 0.0, z: 0.0 })
   }
+
+  #[test]
+  fn jitter_with_zero_amount_keeps_direction() {
+    let v = Vector { x: 0.0, y: 1.0, z: 0.0 };
+
+    assert_eq!(v.jitter(0.0), v);
+  }
+
+  #[test]
+  fn jitter_stays_inside_lobe() {
+    let v = Vector { x: 0.0, y: 1.0, z: 0.0 };
+
+    for _ in 0..100 {
+      let j = v.jitter(0.5);
+
+      assert!(equal(j.magnitude(), 1.0));
+      assert!(j.dot(&v) > 0.0);
+    }
+  }
 }
@@ -1,7 +1,7 @@
 use crate::sphere::Sphere;
 use crate::point_light::PointLight;
 use crate::canvas::Color;
-use crate::material::Material;
+use crate::material::{Material, RED_WAVELENGTH, GREEN_WAVELENGTH, BLUE_WAVELENGTH};
 use crate::matrix::Matrix;
 use crate::point::Point;
 use crate::ray::Ray;
@@ -9,6 +9,9 @@ use crate::intersection::Intersection;
 use crate::intersection::Computations;
 use crate::vector::Vector;
 use crate::object::{Object, Intersectable};
+use crate::medium::Medium;
+use crate::rng::random;
+use crate::utils::EPSILON;
 
 use std::cmp::Ordering::Equal;
 
@@ -16,6 +19,7 @@ use std::cmp::Ordering::Equal;
 pub struct World {
   pub objects: Vec<Object>,
   pub lights: Vec<PointLight>,
+  pub fog: Option<Medium>,
 }
 
 impl World {
@@ -23,6 +27,7 @@ impl World {
     World {
       objects: vec![],
       lights: vec![],
+      fog: None,
     }
   }
 
@@ -41,18 +46,19 @@ impl World {
 
     World {
       objects: vec![Object::Sphere(s1), Object::Sphere(s2)],
-      lights: vec![light]
+      lights: vec![light],
+      fog: None,
     }
   }
 
-  pub fn intersect(&self, r: Ray) -> Vec<Intersection> {
+  pub fn intersect(&self, r: Ray) -> Vec<Intersection<'_>> {
     let mut intersections: Vec<Intersection> = vec![];
 
-    for (_i, object) in self.objects.iter().enumerate() {
+    for (i, object) in self.objects.iter().enumerate() {
       let mut object_intersections = object.intersect(r);
 
       for t in object_intersections {
-        intersections.push(Intersection { time: t, object: object });
+        intersections.push(Intersection { time: t, object: object, index: i });
       }
     }
 
@@ -66,14 +72,40 @@ impl World {
       return Color { r: 0.0, g: 0.0, b: 0.0 };
     }
 
-    if comps.object.material().reflective == 0.0 {
+    let material = comps.object.material();
+
+    if material.reflective == 0.0 {
       return Color { r: 0.0, g: 0.0, b: 0.0 };
     }
 
-    let reflect_ray = Ray { origin: comps.over_point, direction: comps.reflect_vector };
-    let color = self.color_at(reflect_ray, remaining - 1);
+    let reflect_ray = Ray { origin: comps.over_point, direction: comps.reflect_vector, time: comps.ray_time };
+    let color = self.glossy_color(reflect_ray, comps.normal, &material, comps.n1_medium, remaining);
+
+    color * material.reflective
+  }
+
+  // Traces a single ray for smooth materials. Rough materials average several
+  // rays jittered around its direction instead, mirroring any that end up on
+  // the wrong side of the surface back towards `side`.
+  fn glossy_color(&self, r: Ray, side: Vector, material: &Material, medium: Option<Material>, remaining: u8) -> Color {
+    if material.roughness == 0.0 {
+      return self.color_through(r, medium, remaining - 1);
+    }
+
+    let samples = material.glossy_samples.max(1);
+    let mut color = Color { r: 0.0, g: 0.0, b: 0.0 };
+
+    for _ in 0..samples {
+      let mut jittered = r.direction.jitter(material.roughness);
+
+      if jittered.dot(&side) < 0.0 {
+        jittered = jittered.reflect(side);
+      }
+
+      color = color + self.color_through(Ray { direction: jittered, ..r }, medium, remaining - 1);
+    }
 
-    color * comps.object.material().reflective
+    color * (1.0 / samples as f64)
   }
 
   pub fn schlick(comps: &Computations) -> f64 {
@@ -102,70 +134,262 @@ impl World {
       return Color { r: 0.0, g: 0.0, b: 0.0 };
     }
 
-    if comps.object.material().transparency == 0.0 {
+    let material = comps.object.material();
+
+    if material.transparency == 0.0 {
       return Color { r: 0.0, g: 0.0, b: 0.0 }
     }
 
-    let n_ratio = comps.n1 / comps.n2;
+    if !World::disperses(comps) {
+      return self.refract(comps, comps.n1, comps.n2, &material, remaining) * material.transparency;
+    }
+
+    // Dispersive media bend each wavelength differently, so trace one ray per
+    // colour channel and keep only that channel from each.
+    let index_at = |m: Option<Material>, wavelength: f64| m.map_or(1.0, |m| m.refractive_index_at(wavelength));
+    let channel = |wavelength: f64| {
+      let n1 = index_at(comps.n1_medium, wavelength);
+      let n2 = index_at(comps.n2_medium, wavelength);
+
+      self.refract(comps, n1, n2, &material, remaining)
+    };
+
+    let color = Color {
+      r: channel(RED_WAVELENGTH).r,
+      g: channel(GREEN_WAVELENGTH).g,
+      b: channel(BLUE_WAVELENGTH).b,
+    };
+
+    color * material.transparency
+  }
+
+  fn refract(&self, comps: &Computations, n1: f64, n2: f64, material: &Material, remaining: u8) -> Color {
+    match World::refraction_ray(comps, n1, n2) {
+      Some(refract_ray) => self.glossy_color(refract_ray, -comps.normal, material, comps.n2_medium, remaining),
+      None => Color { r: 0.0, g: 0.0, b: 0.0 },
+    }
+  }
+
+  // Whether the light refracted at the hit splits by wavelength.
+  pub fn disperses(comps: &Computations) -> bool {
+    let is_dispersive = |m: Option<Material>| m.is_some_and(|m| m.dispersion != 0.0);
+
+    is_dispersive(comps.n1_medium) || is_dispersive(comps.n2_medium)
+  }
+
+  // None under total internal reflection
+  pub fn refraction_ray(comps: &Computations, n1: f64, n2: f64) -> Option<Ray> {
+    let n_ratio = n1 / n2;
     let cos_i = comps.eye_vector.dot(&comps.normal);
     let sin2_t = n_ratio.powi(2) * (1.0 - cos_i.powi(2));
 
     if sin2_t > 1.0 {
-      return Color { r: 0.0, g: 0.0, b: 0.0 }
+      return None
     }
 
     let cos_t = (1.0 - sin2_t).sqrt();
 
     let direction = comps.normal * (n_ratio * cos_i - cos_t) - comps.eye_vector * n_ratio;
 
-    let refract_ray = Ray { origin: comps.under_point, direction };
+    Some(Ray { origin: comps.under_point, direction, time: comps.ray_time })
+  }
+
+  pub fn shade_hit(&self, comps: Computations, remaining: u8) -> Color {
+    let (direct, indirect) = self.direct_and_indirect(&comps, remaining);
 
-    let color = self.color_at(refract_ray, remaining - 1) * comps.object.material().transparency;
+    direct + indirect
+  }
 
-    color
+  // The two halves of `shade_hit`: light arriving straight from the
+  // lights, ambient included, and the reflected and refracted light.
+  pub fn direct_and_indirect(&self, comps: &Computations, remaining: u8) -> (Color, Color) {
+    let (direct, reflected, refracted) = self.shading(comps, remaining, |_, _, _| {});
+
+    (direct, reflected + refracted)
   }
 
-  pub fn shade_hit(&self, comps: Computations, remaining: u8) -> Color {
-    let mut color = Color { r: 0.0, g: 0.0, b: 0.0 };
+  // The direct, reflected and refracted light at a hit, the last two
+  // already weighted by the Fresnel effect. `on_light` is told whether
+  // each light is blocked and what it gives the hit.
+  pub fn shading(&self, comps: &Computations, remaining: u8, mut on_light: impl FnMut(PointLight, bool, Color)) -> (Color, Color, Color) {
+    let mut direct = Color { r: 0.0, g: 0.0, b: 0.0 };
 
-    for (_i, light) in self.lights.iter().enumerate() {
-      let in_shadow = self.is_shadowed(*light, comps.over_point);
-      color = color + comps.object.material().lighting_with_object(comps.object, *light, comps.point, comps.eye_vector, comps.normal, in_shadow);
+    for light in self.lights.iter() {
+      let (in_shadow, contribution) = self.light_contribution(comps, *light);
+      on_light(*light, in_shadow, contribution);
+      direct = direct + contribution;
     }
 
-    let reflected = self.reflected_color(&comps, remaining);
-    let refracted = self.refracted_color(&comps, remaining);
+    let reflected = self.reflected_color(comps, remaining);
+    let refracted = self.refracted_color(comps, remaining);
+    let (reflectance, transmittance) = World::fresnel_weights(comps);
+
+    (direct, reflected * reflectance, refracted * transmittance)
+  }
+
+  // Whether the light is blocked, and the direct lighting it gives the hit.
+  pub fn light_contribution(&self, comps: &Computations, light: PointLight) -> (bool, Color) {
+    let in_shadow = self.is_shadowed(light, comps.over_point, comps.ray_time);
+    let light = match self.fog {
+      Some(fog) => PointLight { intensity: light.intensity * fog.transmittance((light.position - comps.point).magnitude()), ..light },
+      None => light,
+    };
+
+    (in_shadow, comps.object.material().lighting_with_object(&comps.object.at_time(comps.ray_time), light, comps.point, comps.eye_vector, comps.normal, in_shadow))
+  }
 
+  // How much of the reflected and refracted colours end up in the hit's
+  // colour. Materials that are both reflective and transparent split
+  // between the two by the Fresnel effect.
+  pub fn fresnel_weights(comps: &Computations) -> (f64, f64) {
     let material = comps.object.material();
+
     if material.reflective > 0.0 && material.transparency > 0.0 {
-      let reflectance = World::schlick(&comps);
+      let reflectance = World::schlick(comps);
 
-      return color + (reflected * reflectance) + (refracted * (1.0 - reflectance));
+      return (reflectance, 1.0 - reflectance);
     }
 
-    color + reflected + refracted
+    (1.0, 1.0)
   }
 
   pub fn color_at(&self, r: Ray, remaining: u8) -> Color {
+    self.color_through(r, None, remaining)
+  }
+
+  // Like color_at, but for a ray travelling inside `medium`, whose colour is
+  // absorbed over the distance to the hit. Rays outside every object pass
+  // through the world's fog, rays inside an object through its volume.
+  pub fn color_through(&self, r: Ray, medium: Option<Material>, remaining: u8) -> Color {
+    self.color_of_intersections(r, self.intersect(r), medium, remaining)
+  }
+
+  // For rays leaving the camera: the colour and how much of the background
+  // the ray covers. Holdout objects cut a black, transparent hole. Other
+  // rays see them as black, so they don't show up in reflections either.
+  pub fn color_and_alpha_at(&self, r: Ray, remaining: u8) -> (Color, f64) {
+    let (color, alpha, _) = self.trace_primary(r, remaining);
+
+    (color, alpha)
+  }
+
+  // Like color_and_alpha_at, but also returns the primary hit and the direct
+  // and indirect light it was shaded with, for anything that wants to know
+  // more about it than its colour. None for misses and holdouts.
+  pub fn trace_primary(&self, r: Ray, remaining: u8) -> (Color, f64, Option<(Computations<'_>, Color, Color)>) {
     let intersections = self.intersect(r);
-    let hit = Intersection::hit(intersections.clone());
 
-    if hit.is_none() {
-      return Color { r: 0.0, g: 0.0, b: 0.0 }
+    match Intersection::hit(intersections.clone()) {
+      Some(hit) if hit.object.holdout() => (Color { r: 0.0, g: 0.0, b: 0.0 }, 0.0, None),
+      Some(hit) => {
+        let distance = hit.time * r.direction.magnitude();
+        let comps = hit.prepare_computations_with_intersections(r, intersections);
+        let (direct, indirect) = self.direct_and_indirect(&comps, remaining);
+
+        (self.along_ray(r, direct + indirect, Some(distance), None), 1.0, Some((comps, direct, indirect)))
+      }
+      None => (self.along_ray(r, Color { r: 0.0, g: 0.0, b: 0.0 }, None, None), 0.0, None),
+    }
+  }
+
+  fn color_of_intersections(&self, r: Ray, intersections: Vec<Intersection>, medium: Option<Material>, remaining: u8) -> Color {
+    match Intersection::hit(intersections.clone()) {
+      Some(hit) => {
+        let distance = hit.time * r.direction.magnitude();
+        let shaded = if hit.object.holdout() {
+          Color { r: 0.0, g: 0.0, b: 0.0 }
+        } else {
+          self.shade_hit(hit.prepare_computations_with_intersections(r, intersections), remaining)
+        };
+
+        self.along_ray(r, shaded, Some(distance), medium)
+      }
+      None => self.along_ray(r, Color { r: 0.0, g: 0.0, b: 0.0 }, None, medium),
+    }
+  }
+
+  // What reaches the ray's origin of `color`, shaded `distance` along a ray
+  // travelling inside `medium`, together with the light the medium's volume
+  // scatters towards the ray on the way. A distance of None is a ray that
+  // leaves the scene.
+  pub fn along_ray(&self, r: Ray, color: Color, distance: Option<f64>, medium: Option<Material>) -> Color {
+    let volume = match medium {
+      Some(m) => m.volume,
+      None => self.fog,
+    };
+
+    let distance = match distance {
+      Some(d) => d,
+      None => {
+        return match volume {
+          Some(v) => self.in_scattered(r, v, medium.is_some(), v.max_distance()),
+          None => Color { r: 0.0, g: 0.0, b: 0.0 },
+        }
+      }
+    };
+
+    let mut color = color;
+    if let Some(m) = medium {
+      color = color * m.transmittance(distance);
+    }
+
+    match volume {
+      Some(v) => color * v.transmittance(distance) + self.in_scattered(r, v, medium.is_some(), distance),
+      None => color,
+    }
+  }
+
+  // Single scattering: marches along the first `distance` units of the ray,
+  // gathering light scattered towards it from every light that isn't
+  // shadowed. Light reaching a bounded volume is only attenuated up to the
+  // volume's boundary, the first surface towards the light, and only
+  // shadowed by what lies beyond it.
+  fn in_scattered(&self, r: Ray, volume: Medium, bounded: bool, distance: f64) -> Color {
+    let direction = r.direction.normalize();
+    let steps = volume.steps.max(1);
+    let step = distance / steps as f64;
+    let mut color = Color { r: 0.0, g: 0.0, b: 0.0 };
+
+    for i in 0..steps {
+      let t = (i as f64 + random()) * step;
+      let point = r.origin + direction * t;
+
+      for light in self.lights.iter() {
+        let light_distance = (light.position - point).magnitude();
+        let to_light = (light.position - point).normalize();
+        let (travelled, shadow_from) = if bounded {
+          let boundary = self.distance_to_boundary(Ray { origin: point, direction: to_light, time: r.time }).min(light_distance);
+
+          (boundary, point + to_light * (boundary + EPSILON))
+        } else {
+          (light_distance, point)
+        };
+
+        if self.is_shadowed(*light, shadow_from, r.time) {
+          continue;
+        }
+
+        let amount = volume.transmittance(t) * volume.transmittance(travelled) * volume.phase(direction.dot(&to_light)) * volume.scattering * step;
+        color = color + light.intensity * amount;
+      }
     }
 
-    let unwrapped_hit = hit.unwrap();
-    let comps = unwrapped_hit.prepare_computations_with_intersections(r, intersections);
+    color * volume.color
+  }
 
-    self.shade_hit(comps, remaining)
+  fn distance_to_boundary(&self, r: Ray) -> f64 {
+    match Intersection::hit(self.intersect(r)) {
+      Some(i) => i.time,
+      None => f64::INFINITY,
+    }
   }
 
-  pub fn is_shadowed(&self, light: PointLight, point: Point) -> bool {
+  pub fn is_shadowed(&self, light: PointLight, point: Point, time: f64) -> bool {
     let v = light.position - point;
     let distance = v.magnitude();
     let direction = v.normalize();
 
-    let r = Ray { origin: point, direction };
+    let r = Ray { origin: point, direction, time };
     let intersections = self.intersect(r);
 
     let hit = Intersection::shadow_hit(intersections);
@@ -192,6 +416,10 @@ mod tests {
   use crate::intersection::Intersection;
   use crate::utils::EPSILON;
   use crate::object::Object;
+  use crate::plane::Plane;
+  use crate::cube::Cube;
+  use crate::medium::Medium;
+  use std::f64::consts::{FRAC_1_SQRT_2, SQRT_2};
 
   #[test]
   fn empty_world() {
@@ -227,7 +455,7 @@ mod tests {
   #[test]
   fn intersect_a_world_with_a_ray() {
     let w = World::default();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let intersections = w.intersect(r);
 
@@ -241,10 +469,10 @@ mod tests {
   #[test]
   fn shading_an_intersection() {
     let w = World::default();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let shape = &w.objects[0];
-    let i = Intersection { time: 4.0, object: shape };
+    let i = Intersection { time: 4.0, object: shape, index: 0 };
 
     let comps = i.prepare_computations(r);
 
@@ -260,10 +488,10 @@ mod tests {
     let light = PointLight { position: Point { x: 0.0, y: 0.25, z: 0.0 }, intensity: Color { r: 1.0, g: 1.0, b: 1.0 } };
     w.lights = vec![light];
 
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let shape = &w.objects[1];
-    let i = Intersection { time: 0.5, object: shape };
+    let i = Intersection { time: 0.5, object: shape, index: 1 };
 
     let comps = i.prepare_computations(r);
 
@@ -275,7 +503,7 @@ mod tests {
   #[test]
   fn color_when_ray_misses() {
     let w = World::default();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
 
     let c = w.color_at(r, 5);
 
@@ -285,7 +513,7 @@ mod tests {
   #[test]
   fn color_when_ray_hits() {
     let w = World::default();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
 
     let c = w.color_at(r, 5);
 
@@ -295,7 +523,7 @@ mod tests {
   #[test]
   fn color_when_intersection_behind_ray() {
     let mut w = World::default();
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.75 }, direction: Vector { x: 0.0, y: 0.0, z: -1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 0.75 }, direction: Vector { x: 0.0, y: 0.0, z: -1.0 }, time: 0.0 };
 
     let mut s1_material = Material::new();
     s1_material.color = Color { r: 0.8, g: 1.0, b: 0.6 };
@@ -324,7 +552,7 @@ mod tests {
     let l = w.lights[0];
     let p = Point { x: 0.0, y: 10.0, z: 0.0 };
 
-    assert_eq!(w.is_shadowed(l, p), false);
+    assert!(!w.is_shadowed(l, p, 0.0));
   }
 
   #[test]
@@ -333,7 +561,7 @@ mod tests {
     let l = w.lights[0];
     let p = Point { x: 10.0, y: -10.0, z: 10.0 };
 
-    assert_eq!(w.is_shadowed(l, p), true);
+    assert!(w.is_shadowed(l, p, 0.0));
   }
 
   #[test]
@@ -342,7 +570,7 @@ mod tests {
     let l = w.lights[0];
     let p = Point { x: -20.0, y: 20.0, z: -20.0 };
 
-    assert_eq!(w.is_shadowed(l, p), false);
+    assert!(!w.is_shadowed(l, p, 0.0));
   }
 
   #[test]
@@ -351,7 +579,7 @@ mod tests {
     let l = w.lights[0];
     let p = Point { x: -2.0, y: 2.0, z: -2.0 };
 
-    assert_eq!(w.is_shadowed(l, p), false);
+    assert!(!w.is_shadowed(l, p, 0.0));
   }
 
   #[test]
@@ -366,8 +594,8 @@ mod tests {
 
     w.objects = vec![Object::Sphere(s1), Object::Sphere(s2)];
 
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
-    let i = Intersection { time: 4.0, object: &Object::Sphere(s2) };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: 5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+    let i = Intersection { time: 4.0, object: &Object::Sphere(s2), index: 0 };
     let comps = i.prepare_computations(r);
 
     let c = w.shade_hit(comps, 5);
@@ -377,15 +605,302 @@ mod tests {
 
   #[test]
   fn the_hit_should_offset_the_point() {
-    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 } };
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
     let mut s = Sphere::new();
     s.transform = Matrix::translate(0.0, 0.0, 1.0);
 
-    let i = Intersection { time: 5.0, object: &Object::Sphere(s) };
+    let i = Intersection { time: 5.0, object: &Object::Sphere(s), index: 0 };
     let comps = i.prepare_computations(r);
 
-    assert_eq!(comps.over_point.z < -(EPSILON / 2.0), true);
-    assert_eq!(comps.point.z > comps.over_point.z, true);
+    assert!(comps.over_point.z < -(EPSILON / 2.0));
+    assert!(comps.point.z > comps.over_point.z);
+
+  }
+
+  fn glossy_test_world(roughness: f64) -> World {
+    let mut floor = Plane::new();
+    floor.material.reflective = 0.5;
+    floor.material.roughness = roughness;
+
+    let mut sky = Sphere::new();
+    sky.transform = Matrix::scale_linear(10.0);
+    sky.material.color = Color { r: 0.2, g: 0.4, b: 0.6 };
+    sky.material.ambient = 1.0;
+    sky.material.diffuse = 0.0;
+    sky.material.specular = 0.0;
+
+    let mut w = World::default();
+    w.objects = vec![Object::Plane(floor), Object::Sphere(sky)];
+    w
+  }
+
+  #[test]
+  fn rough_reflection_of_uniform_surroundings_matches_smooth_reflection() {
+    let r = Ray { origin: Point { x: 0.0, y: 1.0, z: -1.0 }, direction: Vector { x: 0.0, y: -FRAC_1_SQRT_2, z: FRAC_1_SQRT_2 }, time: 0.0 };
+
+    let smooth = glossy_test_world(0.0);
+    let i = Intersection { time: SQRT_2, object: &smooth.objects[0], index: 0 };
+    let smooth_color = smooth.reflected_color(&i.prepare_computations(r), 5);
+
+    let rough = glossy_test_world(0.8);
+    let i = Intersection { time: SQRT_2, object: &rough.objects[0], index: 0 };
+    let rough_color = rough.reflected_color(&i.prepare_computations(r), 5);
+
+    assert_eq!(smooth_color, Color { r: 0.1, g: 0.2, b: 0.3 });
+    assert_eq!(rough_color, smooth_color);
+  }
+
+  #[test]
+  fn rough_material_without_reflection_reflects_nothing() {
+    let mut w = glossy_test_world(0.8);
+    if let Object::Plane(ref mut p) = w.objects[0] {
+      p.material.reflective = 0.0;
+    }
+
+    let r = Ray { origin: Point { x: 0.0, y: 1.0, z: -1.0 }, direction: Vector { x: 0.0, y: -FRAC_1_SQRT_2, z: FRAC_1_SQRT_2 }, time: 0.0 };
+    let i = Intersection { time: SQRT_2, object: &w.objects[0], index: 0 };
+
+    assert_eq!(w.reflected_color(&i.prepare_computations(r), 5), Color { r: 0.0, g: 0.0, b: 0.0 });
+  }
+
+  // A bright ball on a black background, so that anything blurring the
+  // view of it spreads its light into neighbouring directions
+  fn lamp_world(object: Object, lamp_center: Point) -> World {
+    let mut lamp = Sphere::new();
+    lamp.transform = Matrix::translate(lamp_center.x, lamp_center.y, lamp_center.z);
+    lamp.material.ambient = 1.0;
+    lamp.material.diffuse = 0.0;
+    lamp.material.specular = 0.0;
+
+    let mut w = World::default();
+    w.objects = vec![object, Object::Sphere(lamp)];
+    w
+  }
+
+  #[test]
+  fn rough_reflection_blurs_neighbouring_directions_together() {
+    let floor = |roughness: f64| {
+      let mut p = Plane::new();
+      p.material.reflective = 0.5;
+      p.material.roughness = roughness;
+      p.material.glossy_samples = 64;
+      lamp_world(Object::Plane(p), Point { x: 0.0, y: 3.0, z: 3.0 })
+    };
+    let reflected = |w: &World, x: f64| {
+      let r = Ray { origin: Point { x, y: 1.0, z: -1.0 }, direction: Vector { x: 0.0, y: -FRAC_1_SQRT_2, z: FRAC_1_SQRT_2 }, time: 0.0 };
+      let i = Intersection { time: SQRT_2, object: &w.objects[0], index: 0 };
+      w.reflected_color(&i.prepare_computations(r), 5)
+    };
+    crate::rng::seed(1);
+
+    let (smooth, rough) = (floor(0.0), floor(0.8));
+
+    // Straight at the lamp, and far enough beside it that a mirror misses
+    assert_eq!(reflected(&smooth, 0.0), Color { r: 0.5, g: 0.5, b: 0.5 });
+    assert_eq!(reflected(&smooth, 2.0), Color { r: 0.0, g: 0.0, b: 0.0 });
+    assert!(reflected(&rough, 0.0).r < 0.5);
+    assert!(reflected(&rough, 2.0).r > 0.0);
+  }
+
+  #[test]
+  fn rough_refraction_is_frosted() {
+    let pane = |roughness: f64| {
+      let mut c = Cube::new();
+      c.transform = Matrix::scale(3.0, 3.0, 0.1);
+      c.material.ambient = 0.0;
+      c.material.diffuse = 0.0;
+      c.material.specular = 0.0;
+      c.material.transparency = 1.0;
+      c.material.refractive_index = 1.5;
+      c.material.roughness = roughness;
+      c.material.glossy_samples = 64;
+      lamp_world(Object::Cube(c), Point { x: 0.0, y: 0.0, z: 5.0 })
+    };
+    let seen = |w: &World, x: f64| w.color_at(Ray { origin: Point { x, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 }, 5);
+    crate::rng::seed(1);
+
+    let (clear, frosted) = (pane(0.0), pane(0.3));
+
+    assert_eq!(seen(&clear, 0.0), Color { r: 1.0, g: 1.0, b: 1.0 });
+    assert_eq!(seen(&clear, 1.5), Color { r: 0.0, g: 0.0, b: 0.0 });
+    assert!(seen(&frosted, 0.0).r < 1.0);
+    assert!(seen(&frosted, 1.5).r > 0.0);
+  }
+
+  fn absorbing_slab_world(thickness: f64) -> World {
+    let mut slab = Cube::new();
+    slab.transform = Matrix::scale(1.0, 1.0, thickness / 2.0);
+    slab.material.ambient = 0.0;
+    slab.material.diffuse = 0.0;
+    slab.material.specular = 0.0;
+    slab.material.transparency = 1.0;
+    slab.material.absorption_color = Color { r: 1.0, g: 0.0, b: 0.0 };
+    slab.material.absorption_density = 1.0;
+
+    let mut sky = Sphere::new();
+    sky.transform = Matrix::scale_linear(10.0);
+    sky.material.ambient = 1.0;
+    sky.material.diffuse = 0.0;
+    sky.material.specular = 0.0;
+
+    let mut w = World::default();
+    w.objects = vec![Object::Cube(slab), Object::Sphere(sky)];
+    w
+  }
+
+  #[test]
+  fn refracted_light_is_absorbed_by_path_length_through_the_medium() {
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+
+    let thin = absorbing_slab_world(1.0).color_at(r, 5);
+    let thick = absorbing_slab_world(2.0).color_at(r, 5);
+
+    assert_eq!(thin, Color { r: 1.0, g: (-1.0_f64).exp(), b: (-1.0_f64).exp() });
+    assert_eq!(thick, Color { r: 1.0, g: (-2.0_f64).exp(), b: (-2.0_f64).exp() });
+  }
+
+  #[test]
+  fn dispersion_splits_total_internal_reflection_by_channel() {
+    let mut glass = Sphere::new();
+    glass.material.ambient = 0.0;
+    glass.material.diffuse = 0.0;
+    glass.material.specular = 0.0;
+    glass.material.transparency = 1.0;
+    glass.material.refractive_index = 1.41;
+    glass.material.dispersion = 0.02;
+
+    let mut sky = Sphere::new();
+    sky.transform = Matrix::scale_linear(10.0);
+    sky.material.ambient = 1.0;
+    sky.material.diffuse = 0.0;
+    sky.material.specular = 0.0;
+
+    let mut w = World::default();
+    w.objects = vec![Object::Sphere(glass), Object::Sphere(sky)];
+
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: FRAC_1_SQRT_2 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
+    let xs = w.intersect(r);
+    let hit = Intersection::hit(xs.clone()).unwrap();
+    let comps = hit.prepare_computations_with_intersections(r, xs);
+
+    let c = w.refracted_color(&comps, 5);
+
+    assert_eq!(c, Color { r: 1.0, g: 0.0, b: 0.0 });
+  }
+
+  #[test]
+  fn fog_attenuates_the_color_of_a_hit() {
+    let mut w = World::default();
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+    let clear = w.color_at(r, 5);
+
+    w.fog = Some(Medium::new(0.1, 0.0));
+    let dark = w.color_at(r, 5);
+
+    // Both the eye ray and the light reaching the surface pass through fog
+    let light_distance = (281.0_f64).sqrt();
+    assert_eq!(dark, clear * (-0.1 * (4.0 + light_distance)).exp());
+    assert!(dark.r < clear.r);
+  }
+
+  #[test]
+  fn fog_scatters_light_towards_rays_that_miss_everything() {
+    let mut w = World::new();
+    w.lights = vec![PointLight { position: Point { x: 0.0, y: 5.0, z: 0.0 }, intensity: Color { r: 1.0, g: 1.0, b: 1.0 } }];
+    w.fog = Some(Medium::new(0.0, 0.1));
+
+    let r = Ray { origin: Point { x: -5.0, y: -1.0, z: 0.0 }, direction: Vector { x: 1.0, y: 0.0, z: 0.0 }, time: 0.0 };
+    let c = w.color_at(r, 5);
+
+    assert!(c.r > 0.0);
+    assert_eq!(c.r, c.g);
+  }
+
+  #[test]
+  fn fog_in_shadow_receives_no_scattered_light() {
+    let mut w = World::new();
+    w.lights = vec![PointLight { position: Point { x: 0.0, y: 5.0, z: 0.0 }, intensity: Color { r: 1.0, g: 1.0, b: 1.0 } }];
+    w.fog = Some(Medium::new(0.0, 0.1));
+    w.objects = vec![Object::Plane(Plane::new())];
+
+    let r = Ray { origin: Point { x: -5.0, y: -1.0, z: 0.0 }, direction: Vector { x: 1.0, y: 0.0, z: 0.0 }, time: 0.0 };
+
+    assert_eq!(w.color_at(r, 5), Color { r: 0.0, g: 0.0, b: 0.0 });
+  }
+
+  #[test]
+  fn volumes_inside_objects_scatter_light_from_outside() {
+    let mut ball = Sphere::new();
+    ball.material.ambient = 0.0;
+    ball.material.diffuse = 0.0;
+    ball.material.specular = 0.0;
+    ball.material.transparency = 1.0;
+    ball.material.refractive_index = 1.0;
+
+    let mut w = World::default();
+    w.objects = vec![Object::Sphere(ball)];
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+    let empty = w.color_at(r, 5);
+
+    ball.material.volume = Some(Medium::new(0.0, 0.5));
+    w.objects = vec![Object::Sphere(ball)];
+    let filled = w.color_at(r, 5);
+
+    assert_eq!(empty, Color { r: 0.0, g: 0.0, b: 0.0 });
+    assert!(filled.r > empty.r);
+
+    // A wall between the ball and the light still shadows it
+    let mut wall = Plane::new();
+    wall.transform = Matrix::translate(0.0, 2.0, 0.0);
+    w.objects.push(Object::Plane(wall));
+    assert_eq!(w.color_at(r, 5), Color { r: 0.0, g: 0.0, b: 0.0 });
+  }
+
+  #[test]
+  fn camera_rays_that_miss_are_transparent() {
+    let w = World::default();
+    let miss = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 1.0, z: 0.0 }, time: 0.0 };
+    let hit = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+
+    assert_eq!(w.color_and_alpha_at(miss, 5), (Color { r: 0.0, g: 0.0, b: 0.0 }, 0.0));
+    assert_eq!(w.color_and_alpha_at(hit, 5), (w.color_at(hit, 5), 1.0));
+  }
+
+  #[test]
+  fn holdout_objects_cut_out_alpha_but_still_cast_shadows() {
+    let mut w = World::default();
+    let mut holdout = Sphere::new();
+    holdout.transform = Matrix::translate(0.0, 0.0, -3.0) * Matrix::scale_linear(0.5);
+    holdout.holdout = true;
+    w.objects.push(Object::Sphere(holdout));
+    let r = Ray { origin: Point { x: 0.0, y: 0.0, z: -5.0 }, direction: Vector { x: 0.0, y: 0.0, z: 1.0 }, time: 0.0 };
+
+    assert_eq!(w.color_and_alpha_at(r, 5), (Color { r: 0.0, g: 0.0, b: 0.0 }, 0.0));
+
+    // Behind the holdout as seen from the light
+    w.objects = vec![Object::Sphere(holdout)];
+    assert!(w.is_shadowed(w.lights[0], Point { x: 2.0, y: -2.0, z: -1.6 }, 0.0));
+  }
+
+  #[test]
+  fn holdout_objects_are_black_in_reflections() {
+    let mut mirror = Plane::new();
+    mirror.material.reflective = 1.0;
+    mirror.material.ambient = 0.0;
+    mirror.material.diffuse = 0.0;
+    mirror.material.specular = 0.0;
+
+    let mut ball = Sphere::new();
+    ball.transform = Matrix::translate(0.0, 2.0, 2.0);
+    ball.material.ambient = 1.0;
+
+    let mut w = World::default();
+    w.objects = vec![Object::Plane(mirror), Object::Sphere(ball)];
+    let r = Ray { origin: Point { x: 0.0, y: 2.0, z: -2.0 }, direction: Vector { x: 0.0, y: -FRAC_1_SQRT_2, z: FRAC_1_SQRT_2 }, time: 0.0 };
+    assert!(w.color_at(r, 5).r > 0.0);
 
+    ball.holdout = true;
+    w.objects[1] = Object::Sphere(ball);
+    assert_eq!(w.color_and_alpha_at(r, 5), (Color { r: 0.0, g: 0.0, b: 0.0 }, 1.0));
   }
 }